@@ -0,0 +1,308 @@
+use rand::{rngs::SmallRng, seq::{IteratorRandom, SliceRandom}, SeedableRng};
+use serde::{Deserialize, Serialize};
+use web_time::{Duration, Instant};
+
+use crate::game::{BoardResult, BoardState, GameState, Player, Position};
+
+use super::{mcts::{mcts_until, MctsConfig, Node, SearchProgress}, minimax_expected_outcome};
+
+// how much work an agent may do for one move, stopping at whichever limit is hit first.
+// agents that search a fixed depth ignore it
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchBudget {
+    pub thinking_time: Option<Duration>,
+    pub iterations: Option<u64>,
+    pub simulations: Option<u64>,
+    // caps the size of the search tree, which is kept between moves
+    pub max_nodes: Option<usize>,
+}
+
+impl SearchBudget {
+    pub fn time(thinking_time: Duration) -> Self {
+        SearchBudget { thinking_time: Some(thinking_time), ..SearchBudget::default() }
+    }
+    pub fn iterations(iterations: u64) -> Self {
+        SearchBudget { iterations: Some(iterations), ..SearchBudget::default() }
+    }
+    pub fn is_unlimited(&self) -> bool {
+        self.thinking_time.is_none() && self.iterations.is_none() && self.simulations.is_none() && self.max_nodes.is_none()
+    }
+    pub fn is_exhausted(&self, elapsed: Duration, progress: SearchProgress) -> bool {
+        self.thinking_time.is_some_and(|thinking_time| elapsed >= thinking_time)
+            || self.iterations.is_some_and(|iterations| progress.iterations >= iterations)
+            || self.simulations.is_some_and(|simulations| progress.simulations >= simulations)
+            || self.max_nodes.is_some_and(|max_nodes| progress.nodes >= max_nodes)
+    }
+    // the limits that robust max can't search past to make its choice clearer: the clock and the tree size
+    pub fn is_hard_limit_reached(&self, elapsed: Duration, progress: SearchProgress) -> bool {
+        self.thinking_time.is_some_and(|thinking_time| elapsed >= thinking_time)
+            || self.max_nodes.is_some_and(|max_nodes| progress.nodes >= max_nodes)
+    }
+}
+
+pub trait Agent {
+    fn name(&self) -> String;
+    fn choose_move(&mut self, state: &GameState, budget: SearchBudget) -> Position;
+    // called with every move played in the game, including the agent's own
+    fn notify_move(&mut self, _position: Position) {}
+    // a line about the last search, for showing to the user
+    fn info(&self) -> Option<String> {
+        None
+    }
+    // restarts the agent's random number generator, so that with an iteration budget the same
+    // moves are chosen every time
+    fn seed(&mut self, seed: u64);
+}
+
+const DEFAULT_THINKING_TIME: Duration = Duration::from_secs(1);
+
+pub struct MctsAgent {
+    pub config: MctsConfig,
+    root: Node,
+    rng: SmallRng,
+}
+
+impl MctsAgent {
+    pub fn new(config: MctsConfig) -> Self {
+        MctsAgent { config, root: Node::new(), rng: SmallRng::from_entropy() }
+    }
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+}
+
+impl Default for MctsAgent {
+    fn default() -> Self {
+        MctsAgent::new(MctsConfig::default())
+    }
+}
+
+impl Agent for MctsAgent {
+    fn name(&self) -> String {
+        format!("mcts:{}", self.config.rollouts)
+    }
+    fn choose_move(&mut self, state: &GameState, budget: SearchBudget) -> Position {
+        let budget = if budget.is_unlimited() { SearchBudget::time(DEFAULT_THINKING_TIME) } else { budget };
+        let start = Instant::now();
+        mcts_until(state, &self.config, &mut self.root, &mut self.rng,
+            |_, progress| budget.is_exhausted(start.elapsed(), progress),
+            |_, progress| budget.is_hard_limit_reached(start.elapsed(), progress))
+    }
+    fn notify_move(&mut self, position: Position) {
+        self.root.take_move(position);
+    }
+    fn info(&self) -> Option<String> {
+        let best = self.root.child(self.root.final_move(&self.config)?)?;
+        let mut info = format!("nodes in tree: {}, simulations: {}, expected score: {:.3}",
+            self.root.count_descendants() + 1, self.root.simulations, best.score as f64 / best.simulations as f64);
+        if let Some(solution) = self.root.solution() {
+            info += &format!(", proven {solution}");
+        }
+        Some(info)
+    }
+    fn seed(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+}
+
+pub struct MinimaxAgent {
+    pub depth: u32,
+    pub random_count: u32,
+    rng: SmallRng,
+}
+
+impl MinimaxAgent {
+    pub fn new(depth: u32, random_count: u32) -> Self {
+        MinimaxAgent { depth, random_count, rng: SmallRng::from_entropy() }
+    }
+}
+
+impl Agent for MinimaxAgent {
+    fn name(&self) -> String {
+        format!("minimax:{}", self.depth)
+    }
+    fn choose_move(&mut self, state: &GameState, _budget: SearchBudget) -> Position {
+        let (_, meta, mini) = minimax_expected_outcome(0, self.depth, state, state.turn, self.random_count, &mut self.rng);
+        Position(meta, mini)
+    }
+    fn seed(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+}
+
+pub struct RandomAgent {
+    rng: SmallRng,
+}
+
+impl Default for RandomAgent {
+    fn default() -> Self {
+        RandomAgent { rng: SmallRng::from_entropy() }
+    }
+}
+
+impl Agent for RandomAgent {
+    fn name(&self) -> String {
+        "random".to_string()
+    }
+    fn choose_move(&mut self, state: &GameState, _budget: SearchBudget) -> Position {
+        state.moves().choose(&mut self.rng).unwrap()
+    }
+    fn seed(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+}
+
+// wins the game if it can, otherwise takes the most mini boards while giving the opponent the fewest in reply
+pub struct GreedyAgent {
+    rng: SmallRng,
+}
+
+impl Default for GreedyAgent {
+    fn default() -> Self {
+        GreedyAgent { rng: SmallRng::from_entropy() }
+    }
+}
+
+fn boards_won(state: &GameState, player: Player) -> u32 {
+    let (x, o) = state.mini_boards_won();
+    match player {
+        Player::X => x,
+        Player::O => o,
+    }
+}
+
+impl Agent for GreedyAgent {
+    fn name(&self) -> String {
+        "greedy".to_string()
+    }
+    fn choose_move(&mut self, state: &GameState, _budget: SearchBudget) -> Position {
+        let player = state.turn;
+        let mut game = state.clone();
+        let mut best = (i32::MIN, Vec::new());
+        for position in state.moves() {
+            let before = boards_won(&game, player);
+            let token = game.play(position).unwrap();
+            let score = match token.result {
+                BoardState::Concluded(result) if result == BoardResult::from(player) => return position,
+                BoardState::Concluded(BoardResult::Tie) => 0,
+                BoardState::Concluded(_) => -1000,
+                BoardState::Ongoing => {
+                    let taken = (boards_won(&game, player) - before) as i32;
+                    let opponent_before = boards_won(&game, player.switch());
+                    let mut reply = 0;
+                    for opponent_move in game.moves() {
+                        let opponent_token = game.play(opponent_move).unwrap();
+                        reply = reply.max(match opponent_token.result {
+                            BoardState::Concluded(BoardResult::Tie) => 0,
+                            BoardState::Concluded(_) => 100,
+                            BoardState::Ongoing => (boards_won(&game, player.switch()) - opponent_before) as i32,
+                        });
+                        game.unplace(opponent_token);
+                    }
+                    2 * taken - reply
+                },
+            };
+            game.unplace(token);
+            if score > best.0 {
+                best = (score, vec![position]);
+            } else if score == best.0 {
+                best.1.push(position);
+            }
+        }
+        *best.1.choose(&mut self.rng).unwrap()
+    }
+    fn seed(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::Ruleset;
+
+    use super::*;
+
+    #[test]
+    fn budgets_stop_the_search() {
+        let state = GameState::new(Player::X, Ruleset::default());
+        let mut agent = MctsAgent::default();
+        agent.choose_move(&state, SearchBudget::iterations(500));
+        assert_eq!(agent.root().simulations, 500 * 10);
+
+        let mut agent = MctsAgent::default();
+        agent.choose_move(&state, SearchBudget { simulations: Some(1000), ..SearchBudget::iterations(500) });
+        assert_eq!(agent.root().simulations, 1000);
+
+        let mut agent = MctsAgent::default();
+        agent.choose_move(&state, SearchBudget { max_nodes: Some(300), ..SearchBudget::default() });
+        assert!(agent.root().count_descendants() + 1 >= 300);
+        assert!(agent.root().count_descendants() + 1 < 300 + 81);
+    }
+
+    #[test]
+    fn agents_play_legal_games() {
+        let budget = SearchBudget::time(Duration::from_millis(2));
+        let new_agents = || -> Vec<Box<dyn Agent>> {
+            vec![Box::new(MctsAgent::default()), Box::new(MinimaxAgent::new(1, 1)), Box::new(RandomAgent::default()), Box::new(GreedyAgent::default())]
+        };
+        for x in 0..4 {
+            for o in 0..4 {
+                let (mut x, mut o) = (new_agents().swap_remove(x), new_agents().swap_remove(o));
+                let mut state = GameState::new(Player::X, Ruleset::default());
+                while let BoardState::Ongoing = state.board_state {
+                    let agent = match state.turn {
+                        Player::X => &mut x,
+                        Player::O => &mut o,
+                    };
+                    let position = agent.choose_move(&state, budget);
+                    state.play(position).unwrap();
+                    x.notify_move(position);
+                    o.notify_move(position);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn minimax_does_not_end_the_game_with_a_loss() {
+        // tying the last top row board hands o the game when ties count for both players
+        let state = GameState::from_fen("ooooooxox/6xoo/6ox1/9/9/9/9/9/9 x - t").unwrap();
+        let losing = "i3".parse::<Position>().unwrap();
+        for seed in 0..10 {
+            let mut agent = MinimaxAgent::new(1, 2);
+            agent.seed(seed);
+            assert!(agent.choose_move(&state, SearchBudget::default()) != losing);
+        }
+    }
+
+    #[test]
+    fn seeded_agents_are_deterministic() {
+        let budget = SearchBudget::iterations(200);
+        let new_agents = || -> Vec<Box<dyn Agent>> {
+            vec![Box::new(MctsAgent::default()), Box::new(MinimaxAgent::new(1, 2)), Box::new(RandomAgent::default()), Box::new(GreedyAgent::default())]
+        };
+        let play = |x: usize, o: usize, seed: u64| {
+            let (mut x, mut o) = (new_agents().swap_remove(x), new_agents().swap_remove(o));
+            x.seed(seed);
+            o.seed(seed + 1);
+            let mut state = GameState::new(Player::X, Ruleset::default());
+            let mut moves = Vec::new();
+            while let BoardState::Ongoing = state.board_state {
+                let agent = match state.turn {
+                    Player::X => &mut x,
+                    Player::O => &mut o,
+                };
+                let position = agent.choose_move(&state, budget);
+                state.play(position).unwrap();
+                x.notify_move(position);
+                o.notify_move(position);
+                moves.push(position);
+            }
+            moves
+        };
+        for (x, o) in [(0, 2), (1, 3), (3, 0), (2, 2)] {
+            assert!(play(x, o, 7) == play(x, o, 7));
+        }
+        assert!(play(2, 2, 7) != play(2, 2, 8));
+    }
+}
@@ -1,19 +1,161 @@
-use std::{collections::HashMap, f64::consts::SQRT_2, };
+use std::{collections::{hash_map::Entry, HashMap}, f64::consts::SQRT_2, fmt, str::FromStr, sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}, thread};
 use leptos::logging::log;
 use web_time::{Duration, Instant};
 use nohash_hasher::BuildNoHashHasher;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
 
-use crate::game::{BoardState, GameState, Player, Position};
+use crate::game::{BoardResult, BoardState, GameState, Player, Position};
 
-use super::random_games;
+use super::{random_games, recorded_random_games, PlayedMoves};
 
 
+// a game theoretic value found by the search, for the player who moved into the node, with the number of
+// moves left until the game ends
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Proven {
+    Win(u32),
+    Loss(u32),
+    Draw(u32),
+}
+
+impl Proven {
+    // the same result for the other player
+    pub fn invert(self) -> Proven {
+        match self {
+            Proven::Win(depth) => Proven::Loss(depth),
+            Proven::Loss(depth) => Proven::Win(depth),
+            Proven::Draw(depth) => Proven::Draw(depth),
+        }
+    }
+}
+
+// counts the moves of whoever ends the game, like mate in n, for the player to move
+impl fmt::Display for Proven {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Proven::Win(depth) => write!(f, "win in {}", depth.div_ceil(2)),
+            Proven::Loss(depth) => write!(f, "loss in {}", depth / 2),
+            Proven::Draw(depth) => write!(f, "draw in {}", depth.div_ceil(2)),
+        }
+    }
+}
+
+// how the move is picked from the root's children once the search stops. a proven win is always played,
+// and a proven loss only when nothing else is left
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FinalPolicy {
+    // the highest average score
+    #[default]
+    MaxAverage,
+    // the most simulations
+    MaxVisits,
+    // the highest lower confidence bound, the ucb1 formula with the exploration term taken away
+    SecureChild,
+    // the most simulations, after searching on until that is also the highest average
+    RobustMax,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MctsConfig {
+    // the c in the ucb1 formula, higher explores more
+    pub exploration: f64,
+    // random games played from each new leaf, at least 1
+    pub rollouts: u32,
+    // the simulations a leaf needs before its children are added. at least 1, since a leaf has to be
+    // simulated once before there is anything to back up; 0 is treated as 1
+    pub expansion_threshold: u64,
+    pub final_policy: FinalPolicy,
+    // the value given to a child that hasn't been simulated yet. None tries every child once before
+    // going back to any of them
+    pub first_play_urgency: Option<f64>,
+    // how many simulations of its own a node needs before its average and its amaf average count
+    // equally. zero turns rave off, which is the default: rave is experimental and hasn't beaten plain uct
+    pub rave_equivalence: f64,
+    // searches on native builds use this many threads, each with a tree of its own
+    pub threads: u32,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        MctsConfig {
+            exploration: SQRT_2,
+            rollouts: 10,
+            expansion_threshold: 1,
+            final_policy: FinalPolicy::default(),
+            first_play_urgency: None,
+            rave_equivalence: 0.0,
+            threads: 1,
+        }
+    }
+}
+
+impl MctsConfig {
+    // sets a field by name, for engine options and command line flags. names are case insensitive and
+    // each field has a long and a short name
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("invalid {name}: {value}");
+        match name.to_ascii_lowercase().as_str() {
+            "exploration" | "c" => self.exploration = value.parse().map_err(|_| invalid())?,
+            "rollouts" | "randomcount" => self.rollouts = value.parse::<u32>().ok().filter(|&rollouts| rollouts >= 1).ok_or_else(invalid)?,
+            "expansionthreshold" | "expansion" => self.expansion_threshold = value.parse::<u64>().ok().filter(|&threshold| threshold >= 1).ok_or_else(invalid)?,
+            "finalpolicy" | "policy" => self.final_policy = value.parse()?,
+            "firstplayurgency" | "fpu" => self.first_play_urgency = match value {
+                "none" => None,
+                value => Some(value.parse().map_err(|_| invalid())?),
+            },
+            "raveequivalence" | "rave" => self.rave_equivalence = value.parse().map_err(|_| invalid())?,
+            "threads" => self.threads = value.parse().map_err(|_| invalid())?,
+            _ => return Err(format!("unknown mcts option: {name}")),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FinalPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FinalPolicy::MaxAverage => write!(f, "average"),
+            FinalPolicy::MaxVisits => write!(f, "visits"),
+            FinalPolicy::SecureChild => write!(f, "secure"),
+            FinalPolicy::RobustMax => write!(f, "robust"),
+        }
+    }
+}
+
+impl FromStr for FinalPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "average" | "maxaverage" => Ok(FinalPolicy::MaxAverage),
+            "visits" | "maxvisits" => Ok(FinalPolicy::MaxVisits),
+            "secure" | "securechild" => Ok(FinalPolicy::SecureChild),
+            "robust" | "robustmax" => Ok(FinalPolicy::RobustMax),
+            _ => Err(format!("unknown final policy: {s}")),
+        }
+    }
+}
+
+// what a search found out about one of the root's moves, for combining searches that ran somewhere else
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChildStats {
+    pub action: Position,
+    pub score: i64,
+    pub simulations: u64,
+    pub proven: Option<Proven>,
+}
+
 #[derive(Debug)]
 pub struct Node {
     children: HashMap<Position, Node, BuildNoHashHasher<Position>>,
     pub score: i64,
-    pub simulations: u64
+    pub simulations: u64,
+    pub proven: Option<Proven>,
+    // all-moves-as-first statistics, from every playout through the parent where this move was played
+    // by the same player at any point, from the same perspective as score
+    pub amaf_score: i64,
+    pub amaf_simulations: u64,
 }
 
 impl Node {
@@ -29,14 +171,20 @@ impl Node {
         Node {
             children: HashMap::with_hasher(BuildNoHashHasher::default()),
             score: 0,
-            simulations: 0
+            simulations: 0,
+            proven: None,
+            amaf_score: 0,
+            amaf_simulations: 0,
         }
     }
     fn new_child(&mut self, action: Position) {
         let child = Node {
             children: HashMap::with_hasher(BuildNoHashHasher::default()),
             score: 0,
-            simulations: 0
+            simulations: 0,
+            proven: None,
+            amaf_score: 0,
+            amaf_simulations: 0,
         };
         self.children.insert(action, child);
 
@@ -44,10 +192,158 @@ impl Node {
     fn average_score(&self) -> f64 {
         self.score as f64 / self.simulations as f64
     }
+    // the average blended with the amaf average, which counts for less as the node gets its own simulations
+    fn rave_score(&self, rave_equivalence: f64) -> f64 {
+        if self.amaf_simulations == 0 || rave_equivalence == 0.0 {
+            return self.average_score();
+        }
+        let beta = (rave_equivalence / (3.0 * self.simulations as f64 + rave_equivalence)).sqrt();
+        (1.0 - beta) * self.average_score() + beta * self.amaf_score as f64 / self.amaf_simulations as f64
+    }
+    // credits every child whose move the player to move here went on to play in a playout
+    fn update_amaf(&mut self, player: Player, playouts: &[Playout]) {
+        for (action, child) in self.children.iter_mut() {
+            let bit = 1 << action.index();
+            for playout in playouts {
+                if playout.played[player.index()] & bit != 0 {
+                    child.amaf_score += playout.count as i64 * playout.result as i64 * player as i64;
+                    child.amaf_simulations += playout.count;
+                }
+            }
+        }
+    }
+    // proven results outrank any average, and among them quicker wins and slower losses come first
+    fn value(&self) -> f64 {
+        match self.proven {
+            Some(Proven::Win(depth)) => 2.0 - depth as f64 / 1000.0,
+            Some(Proven::Loss(depth)) => -2.0 + depth as f64 / 1000.0,
+            Some(Proven::Draw(_)) => 0.0,
+            None => self.average_score(),
+        }
+    }
+    // the result of the game with best play from here for the player to move, once the search has proven one
+    pub fn solution(&self) -> Option<Proven> {
+        self.proven.map(Proven::invert)
+    }
+    // a move that wins makes this node a loss for whoever moved into it, and once every move is proven
+    // the best of them decides it
+    fn update_proven(&mut self) {
+        let mut all_proven = true;
+        let mut quickest_win = None;
+        let mut draw = None;
+        let mut slowest_loss = None;
+        for child in self.children.values() {
+            match child.proven {
+                Some(Proven::Win(depth)) => quickest_win = Some(quickest_win.map_or(depth, |quickest: u32| quickest.min(depth))),
+                Some(Proven::Draw(depth)) => draw = Some(draw.map_or(depth, |draw: u32| draw.max(depth))),
+                Some(Proven::Loss(depth)) => slowest_loss = Some(slowest_loss.map_or(depth, |slowest: u32| slowest.max(depth))),
+                None => all_proven = false,
+            }
+        }
+        self.proven = match (quickest_win, draw, slowest_loss) {
+            (Some(depth), _, _) => Some(Proven::Loss(depth + 1)),
+            _ if !all_proven || self.children.is_empty() => None,
+            (None, Some(depth), _) => Some(Proven::Draw(depth + 1)),
+            (None, None, Some(depth)) => Some(Proven::Win(depth + 1)),
+            (None, None, None) => None,
+        };
+    }
+    // adds the statistics of another search from the same position into this tree
+    fn merge(&mut self, other: Node) {
+        self.score += other.score;
+        self.simulations += other.simulations;
+        self.amaf_score += other.amaf_score;
+        self.amaf_simulations += other.amaf_simulations;
+        for (action, child) in other.children {
+            match self.children.entry(action) {
+                Entry::Occupied(entry) => entry.into_mut().merge(child),
+                Entry::Vacant(entry) => {
+                    entry.insert(child);
+                },
+            }
+        }
+        // either tree may have proven more, and the merged children prove at least as much as both
+        if self.has_children() {
+            self.update_proven();
+        } else {
+            self.proven = self.proven.or(other.proven);
+        }
+    }
+    pub fn child_stats(&self) -> Vec<ChildStats> {
+        self.children.iter().map(|(&action, child)| ChildStats {
+            action,
+            score: child.score,
+            simulations: child.simulations,
+            proven: child.proven,
+        }).collect()
+    }
+    // a root with the children of several searches from the same position added together, to pick the move from
+    pub fn from_child_stats(searches: impl IntoIterator<Item = Vec<ChildStats>>) -> Node {
+        let mut root = Node::new();
+        for stats in searches.into_iter().flatten() {
+            let mut child = Node::new();
+            child.score = stats.score;
+            child.simulations = stats.simulations;
+            child.proven = stats.proven;
+            root.score -= stats.score;
+            root.simulations += stats.simulations;
+            match root.children.entry(stats.action) {
+                Entry::Occupied(entry) => entry.into_mut().merge(child),
+                Entry::Vacant(entry) => {
+                    entry.insert(child);
+                },
+            }
+        }
+        root.update_proven();
+        root
+    }
     pub fn take_move(&mut self, action: Position) {
-        if let Some(child) = self.children.remove(&action) {
-            *self = child;
+        // only a search root holds just one move out of each symmetric set, so a move may be missing
+        // there. everywhere below it every move is kept, so the replies to a move are always found
+        *self = self.children.remove(&action).unwrap_or_default();
+    }
+    pub fn child(&self, action: Position) -> Option<&Node> {
+        self.children.get(&action)
+    }
+    // the move the config's final policy picks, or None without any children
+    pub fn final_move(&self, config: &MctsConfig) -> Option<Position> {
+        let rank = |node: &Node| match (node.proven, config.final_policy) {
+            (Some(Proven::Win(_)), _) => (2, node.value()),
+            (Some(Proven::Loss(_)), _) => (0, node.value()),
+            (_, FinalPolicy::MaxAverage) => (1, node.value()),
+            (_, FinalPolicy::MaxVisits | FinalPolicy::RobustMax) => (1, node.simulations as f64),
+            // a proven draw is certain, so its bound is its value
+            (Some(Proven::Draw(_)), FinalPolicy::SecureChild) => (1, 0.0),
+            (None, FinalPolicy::SecureChild) if node.simulations == 0 => (1, f64::NEG_INFINITY),
+            (None, FinalPolicy::SecureChild) => (1, node.average_score()
+                - config.exploration * ((self.simulations as f64).ln() / node.simulations as f64).sqrt()),
+        };
+        let result = self.children.iter()
+            .reduce(|max, next| if rank(max.1) < rank(next.1) { next } else { max })?;
+        Some(*result.0)
+    }
+    pub fn best_move(&self) -> Option<Position> {
+        let result = self.children.iter()
+        .reduce(|max, next| {
+            // println!("{:?} {} {}", next.0, next.1.average_score(), next.1.simulations);
+            if max.1.value() < next.1.value() {
+                next
+            } else { 
+                max 
+            }
+        })?;
+        // println!("got an average score of {} with {} simulated games", result.1.average_score(), result.1.simulations);
+        Some(*result.0)
+    }
+    // the line of best moves from here, as far as the tree goes
+    pub fn principal_variation(&self) -> Vec<Position> {
+        let mut line = Vec::new();
+        let mut node = self;
+        while let Some(action) = node.best_move() {
+            line.push(action);
+            node = &node.children[&action];
         }
+        line
     }
     pub fn has_children(&self) -> bool {
         !self.children.is_empty()
@@ -59,88 +355,500 @@ impl Default for Node {
     }
 }
 
-pub fn mcts(starting_board: &GameState, random_count: u32, thinking_time: Duration, root: &mut Node) -> Position {
+pub fn mcts(starting_board: &GameState, config: &MctsConfig, thinking_time: Duration, root: &mut Node, rng: &mut impl Rng) -> Position {
 
     let start = Instant::now();
+    let out_of_time = |_: &Node, _| start.elapsed() > thinking_time;
+    mcts_until(starting_board, config, root, rng, out_of_time, out_of_time)
+
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SearchProgress {
+    // iterations and simulations in this search, and nodes in the whole tree including any that were reused
+    pub iterations: u64,
+    pub simulations: u64,
+    pub nodes: usize,
+}
+
+// keeps searching until stop returns true, which is checked after every iteration. robust max can search
+// on past stop, but never past hard_stop, which is given the progress of the whole search
+pub fn mcts_until(starting_board: &GameState, config: &MctsConfig, root: &mut Node, rng: &mut impl Rng, stop: impl FnMut(&Node, SearchProgress) -> bool, mut hard_stop: impl FnMut(&Node, SearchProgress) -> bool) -> Position {
+
+    // threads can't be started from a browser worker, so the web build always searches on one
+    let progress = if config.threads > 1 && cfg!(not(target_arch = "wasm32")) {
+        parallel_search(starting_board, config, root, rng, stop)
+    } else {
+        search(starting_board, config, root, rng, stop)
+    };
+
+    // robust max goes past the stop until the most simulated child also has the best average, but for at
+    // most half as many iterations again or 100, after which the most simulated child is played anyway
+    if config.final_policy == FinalPolicy::RobustMax && root.final_move(config) != root.best_move() && !hard_stop(root, progress) {
+        let limit = progress.iterations + (progress.iterations / 2).max(100);
+        search(starting_board, config, root, rng, |root, extension| {
+            let total = SearchProgress {
+                iterations: progress.iterations + extension.iterations,
+                simulations: progress.simulations + extension.simulations,
+                nodes: extension.nodes,
+            };
+            hard_stop(root, total) || total.iterations >= limit || root.final_move(config) == root.best_move()
+        });
+    }
+
+    root.final_move(config).unwrap()
+
+}
+
+fn search(starting_board: &GameState, config: &MctsConfig, root: &mut Node, rng: &mut impl Rng, mut stop: impl FnMut(&Node, SearchProgress) -> bool) -> SearchProgress {
+    let mut progress = SearchProgress {
+        nodes: root.count_descendants() as usize + 1,
+        ..SearchProgress::default()
+    };
+    // symmetric moves are left out at the root only, as a subtree found by a symmetric move would need
+    // every move after it mapped back through the symmetry
     if !root.has_children() {
-        let moves = starting_board.get_possible_moves().into_iter();
+        let moves = starting_board.distinct_moves().into_iter();
         for move_ in moves {
-            root.new_child(move_)
+            root.new_child(move_);
+            progress.nodes += 1;
         }
     }
 
+    // a proven root has nothing left to search, and a proven win is played straight away
+    while root.proven.is_none() {
+        let (_, simulations, _) = mcts_iteration(starting_board.clone(), root, config, &mut progress.nodes, rng);
+        progress.iterations += 1;
+        progress.simulations += simulations;
 
-    loop {
-        mcts_iteration(starting_board.clone(), root, random_count);
-
-        if start.elapsed() > thinking_time {
+        if stop(root, progress) {
             break;
         }
     }
+    progress
+}
 
-    let result = root.children.iter()
-    .reduce(|max, next| {
-        // println!("{:?} {} {}", next.0, next.1.average_score(), next.1.simulations);
-        if max.1.average_score() < next.1.average_score() {
-            next
-        } else { 
-            max 
-        }
-    }).unwrap();
-    // println!("got an average score of {} with {} simulated games", result.1.average_score(), result.1.simulations);
-    *result.0
+// root parallelism. the other threads each search a tree of their own from the same position, and those
+// trees are merged into root once stop returns true. stop is called on this thread with everyone's progress
+// added up. how far each thread gets depends on the scheduler, so seeded searches aren't reproducible
+fn parallel_search(starting_board: &GameState, config: &MctsConfig, root: &mut Node, rng: &mut impl Rng, mut stop: impl FnMut(&Node, SearchProgress) -> bool) -> SearchProgress {
+    let done = AtomicBool::new(false);
+    let (iterations, simulations, nodes) = (AtomicU64::new(0), AtomicU64::new(0), AtomicUsize::new(0));
+    let seeds = (1..config.threads).map(|_| rng.gen()).collect::<Vec<u64>>();
+    let mut progress = SearchProgress::default();
+    thread::scope(|s| {
+        let helpers = seeds.iter().map(|&seed| {
+            let (done, iterations, simulations, nodes) = (&done, &iterations, &simulations, &nodes);
+            s.spawn(move || {
+                let mut tree = Node::new();
+                let mut last = SearchProgress::default();
+                search(starting_board, config, &mut tree, &mut SmallRng::seed_from_u64(seed), |_, progress| {
+                    iterations.fetch_add(progress.iterations - last.iterations, Ordering::Relaxed);
+                    simulations.fetch_add(progress.simulations - last.simulations, Ordering::Relaxed);
+                    nodes.fetch_add(progress.nodes - last.nodes, Ordering::Relaxed);
+                    last = progress;
+                    done.load(Ordering::Relaxed)
+                });
+                tree
+            })
+        }).collect::<Vec<_>>();
 
+        progress = search(starting_board, config, root, rng, |root, progress| stop(root, SearchProgress {
+            iterations: progress.iterations + iterations.load(Ordering::Relaxed),
+            simulations: progress.simulations + simulations.load(Ordering::Relaxed),
+            nodes: progress.nodes + nodes.load(Ordering::Relaxed),
+        }));
+        done.store(true, Ordering::Relaxed);
+        for helper in helpers {
+            root.merge(helper.join().unwrap());
+        }
+    });
+    SearchProgress {
+        iterations: progress.iterations + iterations.into_inner(),
+        simulations: progress.simulations + simulations.into_inner(),
+        nodes: root.count_descendants() as usize + 1,
+    }
 }
 
-const EXPLORATION_PARAMETER: f64 = SQRT_2;
+// a finished playout below the node being updated, and the moves each player made on the way
+struct Playout {
+    result: BoardResult,
+    played: PlayedMoves,
+    // how many simulations it stands for
+    count: u64,
+}
 
-fn mcts_iteration(mut game: GameState, node: &mut Node, random_count: u32) -> (i64, u64) {
+fn mcts_iteration(mut game: GameState, node: &mut Node, config: &MctsConfig, nodes: &mut usize, rng: &mut impl Rng) -> (i64, u64, Vec<Playout>) {
     let start = Instant::now();
     if node.has_children() {
         let mut max = (f64::NEG_INFINITY, Position((0, 0), (0, 0)));
         for (action, child) in node.children.iter_mut() {
+            // proven children need no more visits. a node always has an unproven child, or it would be proven itself
+            if child.proven.is_some() {
+                continue;
+            }
             let confidence = if node.simulations == 0 || child.simulations == 0 {
-                f64::INFINITY
+                config.first_play_urgency.unwrap_or(f64::INFINITY)
             } else {
-                child.average_score() + EXPLORATION_PARAMETER * ((node.simulations as f64).ln() / child.simulations as f64).sqrt()
+                child.rave_score(config.rave_equivalence) + config.exploration * ((node.simulations as f64).ln() / child.simulations as f64).sqrt()
             };
 
             if confidence > max.0 {
                 max = (confidence, *action)
             }
         }
+        let turn = game.turn;
         game.place(max.1.0, max.1.1).expect("tried to place from a result of game.get_possible_moves(), and failed");
-        let (score, simulations) = mcts_iteration(game, node.children.get_mut(&max.1).unwrap(), random_count);
+        let (score, simulations, mut playouts) = mcts_iteration(game, node.children.get_mut(&max.1).unwrap(), config, nodes, rng);
         
         node.score += score;
         node.simulations += simulations;
-        (-score, simulations)
+        node.update_proven();
+        if config.rave_equivalence > 0.0 {
+            for playout in &mut playouts {
+                playout.played[turn.index()] |= 1 << max.1.index();
+            }
+            node.update_amaf(turn, &playouts);
+        }
+        (-score, simulations, playouts)
     } else if let BoardState::Concluded(result) = game.board_state {
-        node.score += random_count as i64 * result as i64 * game.turn.switch()  as i64;
+        let random_count = config.rollouts;
+        node.proven = Some(match result {
+            BoardResult::Tie => Proven::Draw(0),
+            result if result == BoardResult::from(game.turn.switch()) => Proven::Win(0),
+            _ => Proven::Loss(0),
+        });
+        // back up only this visit, not everything the node has collected so far
+        let score = random_count as i64 * result as i64 * game.turn.switch()  as i64;
+        node.score += score;
         node.simulations += random_count as u64;
-        (-node.score, node.simulations)
-    } else if node.simulations == 0 {
-        
-        let result = random_games(random_count, 1, &game);
-        let score = (result.0 - result.1) * game.turn.switch() as i32;
+        (-score, random_count as u64, vec![Playout { result, played: [0; 2], count: random_count as u64 }])
+    } else if node.simulations < config.expansion_threshold.max(1) {
+        let random_count = config.rollouts;
+        let (result, playouts) = if config.rave_equivalence > 0.0 {
+            let playouts = recorded_random_games(random_count, &game, rng).into_iter()
+                .map(|(result, played)| Playout { result, played, count: 1 })
+                .collect::<Vec<_>>();
+            (playouts.iter().map(|playout| playout.result as i64).sum::<i64>(), playouts)
+        } else {
+            let result = random_games(random_count, 1, &game, rng);
+            ((result.0 - result.1) as i64, Vec::new())
+        };
+        let score = result * game.turn.switch() as i64;
 
-        node.score += score as i64;
+        node.score += score;
         node.simulations += random_count as u64;
-        (-score as i64, random_count as u64)
+        (-score, random_count as u64, playouts)
     } else {
-        let mut moves = game.get_possible_moves().into_iter();
+        let mut moves = game.moves();
         let first = moves.next().unwrap();
         for move_ in moves {
-            node.new_child(move_)
+            node.new_child(move_);
+            *nodes += 1;
         }
         node.new_child(first);
+        *nodes += 1;
 
+        let turn = game.turn;
         game.place(first.0, first.1).expect("tried to place from a result of game.get_possible_moves(), and failed");
-        let (score, simulations) = mcts_iteration(game, node.children.get_mut(&first).unwrap(), random_count);
+        let (score, simulations, mut playouts) = mcts_iteration(game, node.children.get_mut(&first).unwrap(), config, nodes, rng);
         node.score += score;
         node.simulations += simulations;
-        (-score, simulations)
+        node.update_proven();
+        if config.rave_equivalence > 0.0 {
+            for playout in &mut playouts {
+                playout.played[turn.index()] |= 1 << first.index();
+            }
+            node.update_amaf(turn, &playouts);
+        }
+        (-score, simulations, playouts)
     }
 
 
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use rand::{rngs::SmallRng, seq::IteratorRandom, SeedableRng};
+
+    use crate::game::{tests::random_game, Ruleset};
+
+    use super::*;
+
+    // exhaustive search for the player to move, 1 for a win, 0 for a draw and -1 for a loss
+    fn negamax(game: &mut GameState) -> i32 {
+        let mut best = -1;
+        for position in game.get_possible_moves() {
+            let mover = game.turn;
+            let token = game.play(position).unwrap();
+            let value = match game.board_state {
+                BoardState::Concluded(BoardResult::Tie) => 0,
+                BoardState::Concluded(result) => if result == BoardResult::from(mover) { 1 } else { -1 },
+                BoardState::Ongoing => -negamax(game),
+            };
+            game.unplace(token);
+            best = best.max(value);
+        }
+        best
+    }
+
+    fn open_cells(game: &GameState) -> u32 {
+        let mut count = 0;
+        for y in 0..3 {
+            for x in 0..3 {
+                if let BoardState::Ongoing = game.mini_board_state((x, y)) {
+                    count += game.mini_board((x, y)).empty_spaces().count_ones();
+                }
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn solver_agrees_with_exhaustive_search() {
+        let mut rng = SmallRng::seed_from_u64(3);
+        let mut solved = 0;
+        for _ in 0..60 {
+            let mut game = GameState::new(Player::X, Ruleset::default());
+            let mut history = Vec::new();
+            for position in random_game(&mut rng, &game) {
+                history.push(game.clone());
+                game.play(position).unwrap();
+            }
+            // the latest position with enough open cells to be interesting, and few enough to search exhaustively
+            let Some(mut game) = history.into_iter().rev().find(|game| open_cells(game) >= 8) else {
+                continue;
+            };
+            if open_cells(&game) > 10 {
+                continue;
+            }
+            let expected = negamax(&mut game);
+
+            let mut root = Node::new();
+            let best_move = mcts_until(&game, &MctsConfig { rollouts: 1, ..MctsConfig::default() }, &mut root, &mut rng, |_, progress| progress.iterations >= 100_000, |_, _| false);
+            let solution = root.solution().expect("the search should solve positions this close to the end");
+            assert_eq!(match solution {
+                Proven::Win(_) => 1,
+                Proven::Draw(_) => 0,
+                Proven::Loss(_) => -1,
+            }, expected);
+
+            // the move it picks has to keep that result
+            let mover = game.turn;
+            game.play(best_move).unwrap();
+            let value = match game.board_state {
+                BoardState::Concluded(BoardResult::Tie) => 0,
+                BoardState::Concluded(result) => if result == BoardResult::from(mover) { 1 } else { -1 },
+                BoardState::Ongoing => -negamax(&mut game),
+            };
+            assert_eq!(value, expected);
+            solved += 1;
+        }
+        assert!(solved >= 20);
+    }
+
+    #[test]
+    fn amaf_counts_moves_played_later() {
+        let mut node = Node::new();
+        let (e5, a1, i9) = ("e5".parse().unwrap(), "a1".parse().unwrap(), "i9".parse().unwrap());
+        for action in [e5, a1, i9] {
+            node.new_child(action);
+        }
+        let played = |moves: &[Position]| moves.iter().fold(0, |bits, position| bits | 1 << position.index());
+        let playouts = [
+            Playout { result: BoardResult::XWin, played: [played(&[e5, a1]), played(&[i9])], count: 1 },
+            Playout { result: BoardResult::OWin, played: [played(&[a1]), 0], count: 3 },
+            Playout { result: BoardResult::Tie, played: [played(&[i9]), played(&[e5])], count: 2 },
+        ];
+        node.update_amaf(Player::X, &playouts);
+
+        let amaf = |action| (node.children[&action].amaf_score, node.children[&action].amaf_simulations);
+        assert_eq!(amaf(e5), (1, 1));
+        assert_eq!(amaf(a1), (-2, 4));
+        // a move only counts for the player who made it
+        assert_eq!(amaf(i9), (0, 2));
+    }
+
+    #[test]
+    fn rave_searches_collect_amaf() {
+        let mut rng = SmallRng::seed_from_u64(5);
+        let game = GameState::new(Player::X, Ruleset::default());
+        let config = MctsConfig { rollouts: 4, rave_equivalence: 300.0, ..MctsConfig::default() };
+        let mut root = Node::new();
+        mcts_until(&game, &config, &mut root, &mut rng, |_, progress| progress.iterations >= 2000, |_, _| false);
+        for child in root.children.values() {
+            // every visit to a child plays its move, and playouts through its siblings often play it too
+            assert!(child.amaf_simulations >= child.simulations);
+        }
+        assert!(root.children.values().any(|child| child.amaf_simulations > child.simulations));
+    }
+
+    #[test]
+    fn config_options_by_name() {
+        let mut config = MctsConfig::default();
+        config.set("Exploration", "0.5").unwrap();
+        config.set("RandomCount", "3").unwrap();
+        config.set("policy", "visits").unwrap();
+        config.set("fpu", "1.2").unwrap();
+        assert_eq!(config, MctsConfig {
+            exploration: 0.5,
+            rollouts: 3,
+            final_policy: FinalPolicy::MaxVisits,
+            first_play_urgency: Some(1.2),
+            ..MctsConfig::default()
+        });
+        config.set("fpu", "none").unwrap();
+        assert_eq!(config.first_play_urgency, None);
+        assert!(config.set("rollouts", "-1").is_err());
+        assert!(config.set("rollouts", "0").is_err());
+        assert!(config.set("expansion", "0").is_err());
+        assert!(config.set("policy", "best").is_err());
+        assert!(config.set("depth", "3").is_err());
+    }
+
+    // x to move, and d2 or f2 lets o win the game straight away. the root is filled in as if d2 had won
+    // every one of its few rollouts, while the other moves are better explored
+    fn lucky_blunder(lucky_simulations: i64) -> (GameState, Node) {
+        let game = GameState::from_fen("1oox2x1o/xo6x/xo1ox1xo1/4xxx2/x1o1o2xo/x1o1xxx1x/o1xxo1oo1/ooooo3o/1o2xxx2 x 2").unwrap();
+        let mut root = Node::new();
+        for (action, score, simulations) in [("e1", 30, 60), ("f1", 20, 50), ("d2", lucky_simulations, lucky_simulations as u64), ("e2", 15, 50), ("f2", -20, 30), ("f3", 10, 40)] {
+            let action = action.parse().unwrap();
+            root.new_child(action);
+            let child = root.children.get_mut(&action).unwrap();
+            child.score = score;
+            child.simulations = simulations;
+            root.simulations += simulations;
+        }
+        (game, root)
+    }
+
+    #[test]
+    fn final_policies_distrust_lucky_children() {
+        let (_, root) = lucky_blunder(10);
+        let policy = |final_policy| root.final_move(&MctsConfig { final_policy, ..MctsConfig::default() }).unwrap().to_string();
+        assert_eq!(policy(FinalPolicy::MaxAverage), "d2");
+        assert_eq!(policy(FinalPolicy::MaxVisits), "e1");
+        assert_eq!(policy(FinalPolicy::SecureChild), "e1");
+
+        // proven results come before any statistics
+        let (_, mut root) = lucky_blunder(10);
+        root.children.get_mut(&"f3".parse().unwrap()).unwrap().proven = Some(Proven::Win(3));
+        root.children.get_mut(&"e1".parse().unwrap()).unwrap().proven = Some(Proven::Loss(2));
+        for final_policy in [FinalPolicy::MaxAverage, FinalPolicy::MaxVisits, FinalPolicy::SecureChild] {
+            assert_eq!(root.final_move(&MctsConfig { final_policy, ..MctsConfig::default() }).unwrap().to_string(), "f3");
+        }
+    }
+
+    #[test]
+    fn robust_max_searches_until_the_blunder_is_found() {
+        // stopped after a single iteration, the lucky move still has the best average
+        let (game, mut root) = lucky_blunder(30);
+        let best_move = mcts_until(&game, &MctsConfig::default(), &mut root, &mut SmallRng::seed_from_u64(1), |_, progress| progress.iterations >= 1, |_, _| false);
+        assert_eq!(best_move.to_string(), "d2");
+
+        let config = MctsConfig { final_policy: FinalPolicy::RobustMax, ..MctsConfig::default() };
+        let (game, mut root) = lucky_blunder(30);
+        let best_move = mcts_until(&game, &config, &mut root, &mut SmallRng::seed_from_u64(1), |_, progress| progress.iterations >= 1, |_, _| false);
+        assert_eq!(Some(best_move), root.best_move());
+        assert!(!["d2", "f2"].contains(&best_move.to_string().as_str()));
+        assert!(root.child("d2".parse().unwrap()).unwrap().simulations > 40);
+    }
+
+    #[test]
+    fn robust_max_stops_at_the_hard_limit() {
+        let config = MctsConfig { final_policy: FinalPolicy::RobustMax, ..MctsConfig::default() };
+        // stopped from outside, as the engine's stop command does, robust max plays the most simulated move
+        let stopped = Cell::new(false);
+        let (game, mut root) = lucky_blunder(30);
+        let simulations = root.simulations;
+        let best_move = mcts_until(&game, &config, &mut root, &mut SmallRng::seed_from_u64(1), |_, progress| {
+            stopped.set(progress.iterations >= 1);
+            stopped.get()
+        }, |_, _| stopped.get());
+        assert_eq!(best_move.to_string(), "e1");
+        assert_eq!(root.simulations, simulations + 10);
+
+        // and a hard limit reached while searching on ends the extra search there
+        let game = GameState::new(Player::X, Ruleset::default());
+        let search = |hard_limit| {
+            let mut seen = SearchProgress::default();
+            mcts_until(&game, &config, &mut Node::new(), &mut SmallRng::seed_from_u64(1), |_, progress| progress.iterations >= 50, |_, progress| {
+                seen = progress;
+                progress.iterations >= hard_limit
+            });
+            seen.iterations
+        };
+        assert!(search(u64::MAX) > 55);
+        assert_eq!(search(55), 55);
+    }
+
+    #[test]
+    fn parallel_searches_merge_their_trees() {
+        let game = GameState::new(Player::X, Ruleset::default());
+        let config = MctsConfig { threads: 4, ..MctsConfig::default() };
+        let mut root = Node::new();
+        let mut seen = SearchProgress::default();
+        mcts_until(&game, &config, &mut root, &mut SmallRng::seed_from_u64(1), |_, progress| {
+            seen = progress;
+            progress.iterations >= 2000
+        }, |_, _| false);
+        assert!(seen.iterations >= 2000);
+        // the root is never rolled out itself, so every simulation it has went through one of its children
+        assert!(root.simulations >= 2000 * 10);
+        assert_eq!(root.simulations, root.children.values().map(|child| child.simulations).sum::<u64>());
+        assert_eq!(root.score, -root.children.values().map(|child| child.score).sum::<i64>());
+    }
+
+    #[test]
+    fn child_stats_combine_searches() {
+        let game = GameState::new(Player::X, Ruleset::default());
+        let config = MctsConfig::default();
+        let searches = (0..3).map(|seed| {
+            let mut root = Node::new();
+            mcts_until(&game, &config, &mut root, &mut SmallRng::seed_from_u64(seed), |_, progress| progress.iterations >= 300, |_, _| false);
+            root
+        }).collect::<Vec<_>>();
+        let combined = Node::from_child_stats(searches.iter().map(Node::child_stats));
+        assert_eq!(combined.simulations, searches.iter().map(|root| root.simulations).sum::<u64>());
+        assert_eq!(combined.score, searches.iter().map(|root| root.score).sum::<i64>());
+        for (action, child) in &combined.children {
+            assert_eq!(child.simulations, searches.iter().map(|root| root.children[action].simulations).sum::<u64>());
+        }
+        assert_eq!(Node::from_child_stats([searches[0].child_stats()]).final_move(&config), searches[0].final_move(&config));
+
+        // a move proven by any one search counts for all of them
+        let (_, mut root) = lucky_blunder(10);
+        root.children.get_mut(&"f3".parse().unwrap()).unwrap().proven = Some(Proven::Win(0));
+        let (_, other) = lucky_blunder(10);
+        let combined = Node::from_child_stats([root.child_stats(), other.child_stats()]);
+        assert_eq!(combined.final_move(&config).unwrap().to_string(), "f3");
+        assert_eq!(combined.solution(), Some(Proven::Win(1)));
+    }
+
+    #[test]
+    fn replies_keep_the_tree() {
+        let mut state = GameState::new(Player::X, Ruleset::default());
+        let mut root = Node::new();
+        let mut rng = SmallRng::seed_from_u64(2);
+        for _ in 0..4 {
+            let best_move = mcts_until(&state, &MctsConfig::default(), &mut root, &mut rng, |_, progress| progress.iterations >= 3000, |_, _| false);
+            state.play(best_move).unwrap();
+            root.take_move(best_move);
+            // whichever reply is played, symmetric to another one or not, its search carries over
+            assert!(state.moves().all(|reply| root.child(reply).is_some()));
+            let reply = state.moves().choose(&mut rng).unwrap();
+            state.play(reply).unwrap();
+            root.take_move(reply);
+        }
+    }
+
+    #[test]
+    fn proven_results_count_moves() {
+        assert_eq!(Proven::Win(1).to_string(), "win in 1");
+        assert_eq!(Proven::Win(3).to_string(), "win in 2");
+        assert_eq!(Proven::Loss(2).to_string(), "loss in 1");
+        assert_eq!(Proven::Loss(0).invert(), Proven::Win(0));
+    }
+}
@@ -3,35 +3,67 @@ use leptos_workers::worker;
 use serde::{Deserialize, Serialize};
 
 use crate::game::Position;
-use crate::GameState;
+use crate::game::GameState;
 
-use super::mcts::{mcts, Node};
+use super::agent::{Agent, MctsAgent, SearchBudget};
+use super::mcts::{ChildStats, MctsConfig};
+
+const MAX_NODES: usize = 1_000_000;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MctsInit {
+    // in seconds
+    pub thinking_time: f32,
+    pub config: MctsConfig,
+    // how many workers search each move together, which split the node limit between them
+    pub workers: u32,
+}
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum MctsInput {
+    // searches the board, which previous_move was just played to reach, and answers with the statistics of
+    // the root's children so that they can be added to other workers' before a move is picked
     TakeMove {
         board: GameState,
         previous_move: Position 
-    }
+    },
+    // the move that was picked from the last board searched
+    Played(Position),
+    // used from the next move on, keeping the tree
+    SetConfig(MctsConfig),
 }
 #[worker(MctsWorker)]
 pub async fn mcts_worker(
-    init: f32,
+    init: MctsInit,
     rx: leptos_workers::Receiver<MctsInput>,
-    tx: leptos_workers::Sender<Position>
+    tx: leptos_workers::Sender<Vec<ChildStats>>
 ) {
     
 
-    let mut root = Node::new();
+    let mut agent = MctsAgent::new(init.config);
+    // logged so that a reported game can be searched again with the same random numbers
+    let seed = rand::random();
+    agent.seed(seed);
+    log!("ai seed: {seed}");
+    // the tree is kept for the whole game, so cap it to keep the worker's memory bounded
+    let budget = SearchBudget {
+        max_nodes: Some(MAX_NODES / init.workers.max(1) as usize),
+        ..SearchBudget::time(web_time::Duration::from_millis((1000f32*init.thinking_time).round() as u64))
+    };
     while let Ok(input) = rx.recv_async().await {
         match input {
             MctsInput::TakeMove { board, previous_move } => {
-                root.take_move(previous_move);
-                let best_move = mcts(&board, 10, web_time::Duration::from_millis((1000f32*init).round() as u64), &mut root);
-                root.take_move(best_move);
+                agent.notify_move(previous_move);
+                agent.choose_move(&board, budget);
+                let root = agent.root();
                 log!("nodes in tree:{:?}, number of simulations:{:?}, chance of winning:{:?}", root.count_descendants() + 1, root.simulations, root.score as f32/root.simulations as f32);
-                tx.send_async(best_move).await.unwrap();
-            }
+                if let Some(solution) = root.solution() {
+                    log!("proven {solution} for the player");
+                }
+                tx.send_async(root.child_stats()).await.unwrap();
+            },
+            MctsInput::Played(position) => agent.notify_move(position),
+            MctsInput::SetConfig(config) => agent.config = config,
         }
     }
 }
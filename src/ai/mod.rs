@@ -1,40 +1,39 @@
 use std::{sync::atomic::{AtomicI32, Ordering}, thread, time::Instant};
-use rand::seq::IteratorRandom;
+use rand::{rngs::SmallRng, seq::IteratorRandom, Rng, SeedableRng};
 
 use crate::game::{BoardResult, BoardState, Player, GameState};
 
 
+pub mod agent;
 pub mod mcts;
 pub mod mcts_worker;
 
-pub fn minimax_expected_outcome(depth: u32, limit: u32, game: &GameState, player: Player, random_count: u32) -> (i32, (usize, usize), (usize, usize)) {
+pub fn minimax_expected_outcome(depth: u32, limit: u32, game: &GameState, player: Player, random_count: u32, rng: &mut impl Rng) -> (i32, (usize, usize), (usize, usize)) {
     let before = Instant::now();
     // println!("called with: limit = {}, depth = {}", limit, depth);
 
     let moves = game.get_possible_moves();
 
 
-    let mut best_move = (-i32::MAX * player as i32, (0,0), (0,0));
+    // start from a legal move, in case every move scores as a loss
+    let mut best_move = (-i32::MAX * player as i32, moves[0].0, moves[0].1);
+    let mut game = game.clone();
     for move_ in moves {
-        let mut game = game.clone();
-        match game.place(move_.0, move_.1).unwrap() {
-            BoardState::Ongoing => (),
-            BoardState::Concluded(x) => return (x as i32*i32::MAX, move_.0, move_.1)
-        }
-        if depth + 1 < limit {
-            let (score, _, _) = minimax_expected_outcome(depth + 1, limit, &game, player.switch(), random_count);
-            if (score > best_move.0) != (matches!(player, Player::O)) {
-                best_move = (score, move_.0, move_.1);
-            }
-        } else {
-            let result = random_games(random_count, 10, &game);
-            let score = result.0 - result.1;
-            // println!("{score}");
-            if (score > best_move.0) != (matches!(player, Player::O)) {
-                
-                best_move = (score, move_.0, move_.1);
+        let token = game.play(move_).unwrap();
+        let score = match token.result {
+            BoardState::Concluded(result) if result == BoardResult::from(player) => return (result as i32 * i32::MAX, move_.0, move_.1),
+            // some rulesets can end the game in a tie or a loss for the player who makes the last move
+            BoardState::Concluded(result) => result as i32 * i32::MAX,
+            BoardState::Ongoing if depth + 1 < limit => minimax_expected_outcome(depth + 1, limit, &game, player.switch(), random_count, rng).0,
+            BoardState::Ongoing => {
+                let result = random_games(random_count, 10, &game, rng);
+                result.0 - result.1
             }
+        };
+        if (score > best_move.0) != (matches!(player, Player::O)) {
+            best_move = (score, move_.0, move_.1);
         }
+        game.unplace(token);
     }
     // println!("{}'s best move is: {:?}. Depth = {}", player as i32, best_move, depth);
     if depth == 0 {
@@ -45,14 +44,16 @@ pub fn minimax_expected_outcome(depth: u32, limit: u32, game: &GameState, player
 
 }
 
-pub fn random_game(starting_board: &GameState) -> BoardResult {
-    let mut rng = rand::thread_rng();
+// the moves each player made during a game, as bits at Position::index, with X's first
+pub type PlayedMoves = [u128; 2];
+
+pub fn random_game(starting_board: &GameState, rng: &mut impl Rng) -> BoardResult {
     let mut game = starting_board.clone();
     
 
 
     loop {
-        let move_ = game.get_possible_moves().into_iter().choose(&mut rng).unwrap();
+        let move_ = game.moves().choose(rng).unwrap();
         let result = game.place(move_.0, move_.1).unwrap();
         match result {
             BoardState::Concluded(x) => return x,
@@ -64,7 +65,27 @@ pub fn random_game(starting_board: &GameState) -> BoardResult {
     
 
 }
-pub fn random_games(total: u32, thread_count: u32, starting_board: &GameState) -> (i32, i32, i32) {
+
+// a random game that also marks each move in played for the player who made it
+pub fn recorded_random_game(starting_board: &GameState, rng: &mut impl Rng, played: &mut PlayedMoves) -> BoardResult {
+    let mut game = starting_board.clone();
+    loop {
+        let move_ = game.moves().choose(rng).unwrap();
+        played[game.turn.index()] |= 1 << move_.index();
+        if let BoardState::Concluded(x) = game.place(move_.0, move_.1).unwrap() {
+            return x;
+        }
+    }
+}
+// plays total random games on this thread, keeping the moves of each one along with its result
+pub fn recorded_random_games(total: u32, starting_board: &GameState, rng: &mut impl Rng) -> Vec<(BoardResult, PlayedMoves)> {
+    (0..total).map(|_| {
+        let mut played = [0; 2];
+        let result = recorded_random_game(starting_board, rng, &mut played);
+        (result, played)
+    }).collect()
+}
+pub fn random_games(total: u32, thread_count: u32, starting_board: &GameState, rng: &mut impl Rng) -> (i32, i32, i32) {
     //#[cfg(test)]
     //#[cfg(debug_assertions)]
     //let before = Instant::now();
@@ -73,7 +94,7 @@ pub fn random_games(total: u32, thread_count: u32, starting_board: &GameState) -
         let mut o_wins = 0;
         let mut ties = 0;
         for _ in 0..total {
-            match random_game(starting_board) {
+            match random_game(starting_board, rng) {
                 BoardResult::XWin => x_wins += 1,
                 BoardResult::OWin => o_wins += 1,
                 BoardResult::Tie => ties += 1
@@ -91,12 +112,16 @@ pub fn random_games(total: u32, thread_count: u32, starting_board: &GameState) -
         let x_wins = AtomicI32::new(0);
         let o_wins = AtomicI32::new(0);
         let ties = AtomicI32::new(0);
+        // each thread gets its own generator seeded from the caller's, so the totals are still reproducible
+        let seeds = (0..thread_count).map(|_| rng.gen()).collect::<Vec<u64>>();
         thread::scope(|s|
 
-            for _ in 0..thread_count {
-                s.spawn(|| {
+            for &seed in &seeds {
+                let (x_wins, o_wins, ties) = (&x_wins, &o_wins, &ties);
+                s.spawn(move || {
+                    let mut rng = SmallRng::seed_from_u64(seed);
                     for _ in 0..total/thread_count {
-                        match random_game(starting_board) {
+                        match random_game(starting_board, &mut rng) {
                             BoardResult::XWin => x_wins.fetch_add(1, Ordering::Relaxed),
                             BoardResult::OWin => o_wins.fetch_add(1, Ordering::Relaxed),
                             BoardResult::Tie => ties.fetch_add(1, Ordering::Relaxed),
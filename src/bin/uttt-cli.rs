@@ -0,0 +1,230 @@
+use std::{env, io::{self, BufRead, Write}, process};
+
+use ultimate_tic_tac_toe::{ai::{agent::{Agent, GreedyAgent, MctsAgent, MinimaxAgent, RandomAgent, SearchBudget}, mcts::MctsConfig}, game::{BoardResult, BoardState, Game, Player, PlayerType, Position}};
+use web_time::{Duration, Instant};
+
+const USAGE: &str = "usage: uttt-cli [--x PLAYER] [--o PLAYER] [--time SECONDS] [--iterations N] [--threads N] [--seed N] [--o-first] [--stats]
+
+players: human, mcts, minimax:DEPTH, random or greedy. x is human and o is mcts by default
+mcts stops at whichever of --time and --iterations comes first, and thinks for a second if neither is given.
+--threads searches with that many threads, which plays stronger but isn't repeatable with --seed.
+with --seed and --iterations the computer players make the same moves every time
+
+commands on a human turn:
+  e5, 5/5   play a move, as a grid square or as board/cell counted 1-9 from the top left
+  undo      take back your last move, and the computer's reply to it
+  moves     list the legal moves
+  help      show this message
+  quit      leave the game";
+
+const BOARD_NAMES: [&str; 9] = ["top left", "top", "top right", "left", "centre", "right", "bottom left", "bottom", "bottom right"];
+
+struct Options {
+    x: String,
+    o: String,
+    budget: SearchBudget,
+    threads: u32,
+    seed: Option<u64>,
+    starting_player: Player,
+    stats: bool,
+}
+
+// None for a human player
+fn new_agent(spec: &str, threads: u32) -> Result<Option<Box<dyn Agent>>, String> {
+    let agent: Box<dyn Agent> = match spec.split_once(':') {
+        None if spec == "human" => return Ok(None),
+        None if spec == "mcts" || spec == "ai" => Box::new(MctsAgent::new(MctsConfig { threads, ..MctsConfig::default() })),
+        None if spec == "random" => Box::new(RandomAgent::default()),
+        None if spec == "greedy" => Box::new(GreedyAgent::default()),
+        Some(("minimax", depth)) => Box::new(MinimaxAgent::new(depth.parse().map_err(|_| format!("invalid minimax depth: {depth}"))?, 10)),
+        _ => return Err(format!("unknown player type: {spec}")),
+    };
+    Ok(Some(agent))
+}
+
+fn parse_player(value: Option<String>) -> Result<String, String> {
+    let value = value.ok_or("missing player type")?;
+    new_agent(&value, 1)?;
+    Ok(value)
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        x: "human".to_string(),
+        o: "mcts".to_string(),
+        budget: SearchBudget::default(),
+        threads: 1,
+        seed: None,
+        starting_player: Player::X,
+        stats: false,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--x" => options.x = parse_player(args.next())?,
+            "--o" => options.o = parse_player(args.next())?,
+            "--time" => {
+                let seconds = args.next().ok_or("missing thinking time")?;
+                let seconds: f64 = seconds.parse().map_err(|_| format!("invalid thinking time: {seconds}"))?;
+                options.budget.thinking_time = Some(Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid thinking time: {seconds}"))?);
+            },
+            "--iterations" => {
+                let iterations = args.next().ok_or("missing iteration count")?;
+                options.budget.iterations = Some(iterations.parse().map_err(|_| format!("invalid iteration count: {iterations}"))?);
+            },
+            "--threads" => {
+                let threads = args.next().ok_or("missing thread count")?;
+                options.threads = threads.parse().map_err(|_| format!("invalid thread count: {threads}"))?;
+            },
+            "--seed" => {
+                let seed = args.next().ok_or("missing seed")?;
+                options.seed = Some(seed.parse().map_err(|_| format!("invalid seed: {seed}"))?);
+            },
+            "--o-first" => options.starting_player = Player::O,
+            "--stats" => options.stats = true,
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                process::exit(0);
+            },
+            other => return Err(format!("unknown argument: {other}")),
+        }
+    }
+    Ok(options)
+}
+
+fn player_name(player: Player) -> &'static str {
+    match player {
+        Player::X => "x",
+        Player::O => "o",
+    }
+}
+
+fn print_board(game: &Game) {
+    println!();
+    println!("   a   b   c   d   e   f   g   h   i");
+    for (i, line) in game.state.to_string().lines().enumerate() {
+        if i % 2 == 0 {
+            println!("{} {line}", i / 2 + 1);
+        } else {
+            println!("  {line}");
+        }
+    }
+    println!();
+}
+
+fn new_agents(options: &Options) -> [Option<Box<dyn Agent>>; 2] {
+    let mut agents = [new_agent(&options.x, options.threads).unwrap(), new_agent(&options.o, options.threads).unwrap()];
+    if let Some(seed) = options.seed {
+        for (i, agent) in agents.iter_mut().enumerate() {
+            if let Some(agent) = agent {
+                agent.seed(seed + i as u64);
+            }
+        }
+    }
+    agents
+}
+
+fn agent(agents: &mut [Option<Box<dyn Agent>>; 2], player: Player) -> &mut Option<Box<dyn Agent>> {
+    match player {
+        Player::X => &mut agents[0],
+        Player::O => &mut agents[1],
+    }
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{error}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    let mut agents = new_agents(&options);
+    let player_type = |agent: &Option<Box<dyn Agent>>| if agent.is_some() { PlayerType::Mcts } else { PlayerType::Local };
+    let mut game = Game::new(options.starting_player, player_type(&agents[0]), player_type(&agents[1]));
+    game.thinking_time = options.budget.thinking_time;
+    let budget = options.budget;
+    let mut lines = io::stdin().lock().lines();
+
+    loop {
+        print_board(&game);
+        if let BoardState::Concluded(result) = game.state.board_state {
+            let (x_boards, o_boards) = game.state.mini_boards_won();
+            match result {
+                BoardResult::XWin => println!("x wins"),
+                BoardResult::OWin => println!("o wins"),
+                BoardResult::Tie => println!("tie"),
+            }
+            println!("boards won: x {x_boards}, o {o_boards}");
+            break;
+        }
+
+        let turn = game.state.turn;
+        let position = match agent(&mut agents, turn) {
+            Some(agent) => {
+                println!("{} is thinking...", player_name(turn));
+                let start = Instant::now();
+                let position = agent.choose_move(&game.state, budget);
+                println!("{} plays {position}", player_name(turn));
+                if options.stats {
+                    match agent.info() {
+                        Some(info) => println!("{info}, time: {:.2?}", start.elapsed()),
+                        None => println!("time: {:.2?}", start.elapsed()),
+                    }
+                }
+                position
+            },
+            None => {
+                match game.state.next_meta_move {
+                    Some((x, y)) => print!("{} to move in the {} board: ", player_name(turn), BOARD_NAMES[y * 3 + x]),
+                    None => print!("{} to move on any board: ", player_name(turn)),
+                }
+                io::stdout().flush().unwrap();
+                let Some(Ok(line)) = lines.next() else {
+                    break;
+                };
+                match line.trim() {
+                    "" => continue,
+                    "quit" | "exit" => break,
+                    "help" => {
+                        println!("{USAGE}");
+                        continue;
+                    },
+                    "moves" => {
+                        println!("{}", game.state.moves().map(|position| position.to_string()).collect::<Vec<_>>().join(" "));
+                        continue;
+                    },
+                    "undo" => {
+                        if game.undo().is_none() {
+                            println!("nothing to undo");
+                        }
+                        // keep going back until it is a human's turn again
+                        while agent(&mut agents, game.state.turn).is_some() {
+                            if game.undo().is_none() {
+                                break;
+                            }
+                        }
+                        // the agents' search trees no longer match the game
+                        agents = new_agents(&options);
+                        continue;
+                    },
+                    input => match input.parse::<Position>() {
+                        Ok(position) => position,
+                        Err(error) => {
+                            println!("{error}");
+                            continue;
+                        }
+                    }
+                }
+            }
+        };
+
+        if let Err(error) = game.place(position) {
+            println!("{error}");
+            continue;
+        }
+        for agent in agents.iter_mut().flatten() {
+            agent.notify_move(position);
+        }
+    }
+}
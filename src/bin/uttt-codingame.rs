@@ -0,0 +1,91 @@
+// speaks the CodinGame ultimate tic-tac-toe protocol. every turn stdin gives the opponent's last move as
+// "row col" (-1 -1 if we move first), the number of valid actions and then one "row col" line per action,
+// and we answer with "row col". rows and columns count 0-8 over the whole 9x9 grid from the top left
+
+use std::io::{self, BufRead, Lines, StdinLock};
+
+use rand::{rngs::SmallRng, SeedableRng};
+use ultimate_tic_tac_toe::{ai::mcts::{mcts, MctsConfig, Node}, game::{GameState, MetaDraw, Player, Position, Ruleset}};
+use web_time::Duration;
+
+// the limits are 1000ms for the first turn and 100ms after that, less some room for reading input
+const FIRST_TURN_TIME: Duration = Duration::from_millis(900);
+const TURN_TIME: Duration = Duration::from_millis(85);
+
+// the next line holding exactly count numbers, skipping any malformed ones. None once the input ends
+fn read_numbers(lines: &mut Lines<StdinLock>, count: usize) -> Option<Vec<i32>> {
+    loop {
+        let line = lines.next()?.ok()?;
+        match line.split_whitespace().map(|number| number.parse()).collect::<Result<Vec<i32>, _>>() {
+            Ok(numbers) if numbers.len() == count => return Some(numbers),
+            _ => eprintln!("skipping malformed line: {line:?}"),
+        }
+    }
+}
+
+fn position(row: i32, col: i32) -> Option<Position> {
+    if (0..9).contains(&row) && (0..9).contains(&col) {
+        Some(Position::from_grid(col as usize, row as usize))
+    } else {
+        None
+    }
+}
+
+fn main() {
+    // a drawn meta board goes to whoever won more mini boards
+    let ruleset = Ruleset { meta_draw: MetaDraw::MostBoards, ..Ruleset::default() };
+    let mut state = GameState::new(Player::X, ruleset);
+    let mut root = Node::new();
+    let mut rng = SmallRng::from_entropy();
+    let config = MctsConfig::default();
+    let mut first_turn = true;
+    let mut lines = io::stdin().lock().lines();
+
+    while let Some(opponent) = read_numbers(&mut lines, 2) {
+        if let Some(opponent) = position(opponent[0], opponent[1]) {
+            if state.play(opponent).is_err() {
+                eprintln!("the opponent's move {opponent} is illegal here, so the search is out of step:\n{}", state.to_fen());
+            }
+            root.take_move(opponent);
+        }
+        let Some(action_count) = read_numbers(&mut lines, 1) else {
+            break;
+        };
+        let mut actions = Vec::new();
+        for _ in 0..action_count[0] {
+            let Some(action) = read_numbers(&mut lines, 2) else {
+                break;
+            };
+            actions.extend(position(action[0], action[1]));
+        }
+        if actions.is_empty() {
+            eprintln!("no valid actions given");
+            break;
+        }
+        if actions.len() != state.moves().len() || actions.iter().any(|&action| state.moves().all(|position| position != action)) {
+            eprintln!("valid actions don't match the rules engine:\n{}", state.to_fen());
+        }
+
+        let thinking_time = if first_turn { FIRST_TURN_TIME } else { TURN_TIME };
+        first_turn = false;
+        // the referee's list is what counts, so when the two disagree a move that both allow is played,
+        // and failing that any of the referee's, with the tree thrown away
+        let legal = actions.iter().copied().filter(|&action| state.moves().any(|position| position == action)).collect::<Vec<_>>();
+        let best_move = if legal.is_empty() {
+            root = Node::new();
+            actions[0]
+        } else {
+            let best_move = mcts(&state, &config, thinking_time, &mut root, &mut rng);
+            if legal.contains(&best_move) { best_move } else { legal[0] }
+        };
+        eprintln!("simulations: {}, expected score: {:.3}", root.simulations,
+            root.child(best_move).map_or(0.0, |child| child.score as f64 / child.simulations as f64));
+
+        if state.play(best_move).is_err() {
+            eprintln!("our move {best_move} is illegal in the rules engine, so the search is out of step");
+        }
+        root.take_move(best_move);
+        let (col, row) = best_move.grid();
+        println!("{row} {col}");
+    }
+}
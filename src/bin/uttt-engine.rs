@@ -0,0 +1,234 @@
+// a line based engine protocol in the style of UCI, for running the mcts player under external arenas and GUIs
+//
+// uti                                      -> id lines, the options and utiok
+// isready                                  -> readyok
+// newgame                                  forget the search tree
+// setoption name <name> value <value>
+// position (startpos | fen <fen>) [moves <move>...]
+// go [movetime <ms>] [nodes <iterations>] [simulations <n>] [treesize <nodes>] [infinite]
+//                                          -> info lines while searching, then bestmove <move>. the info
+//                                             lines say "proven win in <n>" and so on once the search solves the game.
+//                                             without any limit bestmove waits for stop
+// stop                                     end the search early
+// d                                        print the current position
+// quit
+
+use std::{io::{self, BufRead}, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread::{self, JoinHandle}};
+
+use rand::{rngs::SmallRng, SeedableRng};
+use ultimate_tic_tac_toe::{ai::{agent::SearchBudget, mcts::{mcts_until, MctsConfig, Node, SearchProgress}}, game::{BoardState, GameState, Player, Position, Ruleset}};
+use web_time::{Duration, Instant};
+
+const INFO_INTERVAL: Duration = Duration::from_millis(500);
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Engine {
+    config: MctsConfig,
+    // searches start from this seed when it is set, so a go with a nodes limit always gives the same answer
+    seed: Option<u64>,
+    base: GameState,
+    moves: Vec<Position>,
+    state: GameState,
+    // the tree is moved onto the search thread and handed back when it finishes
+    root: Option<Node>,
+    search: Option<(Arc<AtomicBool>, JoinHandle<Node>)>,
+}
+
+// the pv starts with the move the final policy would play, so that it agrees with bestmove
+fn print_info(root: &Node, config: &MctsConfig, start: Instant, progress: SearchProgress) {
+    let Some(best) = root.final_move(config) else {
+        return;
+    };
+    let child = root.child(best).unwrap();
+    let winrate = (child.score as f64 / child.simulations as f64 + 1.0) / 2.0;
+    let pv = [best].into_iter().chain(child.principal_variation()).map(|position| position.to_string()).collect::<Vec<_>>().join(" ");
+    let proven = root.solution().map_or(String::new(), |solution| format!(" proven {solution}"));
+    println!("info time {} nodes {} visits {} winrate {:.3}{} pv {}", start.elapsed().as_millis(), progress.iterations, root.simulations, winrate, proven, pv);
+}
+
+impl Engine {
+    fn new() -> Self {
+        let state = GameState::new(Player::X, Ruleset::default());
+        Engine {
+            config: MctsConfig::default(),
+            seed: None,
+            base: state.clone(),
+            moves: Vec::new(),
+            state,
+            root: Some(Node::new()),
+            search: None,
+        }
+    }
+
+    fn finish_search(&mut self) {
+        if let Some((stop, handle)) = self.search.take() {
+            stop.store(true, Ordering::Relaxed);
+            self.root = Some(handle.join().unwrap());
+        }
+    }
+
+    fn set_option(&mut self, args: &[&str]) -> Result<(), String> {
+        let value_at = args.iter().position(|&arg| arg == "value").ok_or("missing value")?;
+        let name = args.get(1..value_at).ok_or("missing name")?.join(" ");
+        let value = args[value_at + 1..].join(" ");
+        match name.to_ascii_lowercase().as_str() {
+            "seed" => self.seed = match value.as_str() {
+                "" | "random" => None,
+                value => Some(value.parse().map_err(|_| format!("invalid Seed: {value}"))?),
+            },
+            _ => self.config.set(&name.replace(' ', ""), &value)?,
+        }
+        Ok(())
+    }
+
+    fn set_position(&mut self, args: &[&str]) -> Result<(), String> {
+        let moves_at = args.iter().position(|&arg| arg == "moves").unwrap_or(args.len());
+        let base = match args.first() {
+            Some(&"startpos") => GameState::new(Player::X, Ruleset::default()),
+            Some(&"fen") => GameState::from_fen(&args[1..moves_at].join(" ")).map_err(|error| error.to_string())?,
+            _ => return Err("expected startpos or fen".to_string()),
+        };
+        let mut state = base.clone();
+        let mut moves = Vec::new();
+        for arg in args.iter().skip(moves_at + 1) {
+            let position: Position = arg.parse().map_err(|error| format!("{arg}: {error}"))?;
+            state.play(position).map_err(|error| error.to_string())?;
+            moves.push(position);
+        }
+
+        // keep the tree if this is the last position with more moves played
+        let mut root = self.root.take().unwrap_or_default();
+        if base == self.base && moves.starts_with(&self.moves) {
+            for &position in &moves[self.moves.len()..] {
+                root.take_move(position);
+            }
+        } else {
+            root = Node::new();
+        }
+        self.root = Some(root);
+        self.base = base;
+        self.moves = moves;
+        self.state = state;
+        Ok(())
+    }
+
+    fn go(&mut self, args: &[&str]) -> Result<(), String> {
+        let mut budget = SearchBudget::default();
+        let mut args = args.iter();
+        while let Some(&arg) = args.next() {
+            let mut number = || -> Result<u64, String> {
+                let value = args.next().ok_or(format!("missing {arg}"))?;
+                value.parse().map_err(|_| format!("invalid {arg}: {value}"))
+            };
+            match arg {
+                "movetime" => budget.thinking_time = Some(Duration::from_millis(number()?)),
+                "nodes" => budget.iterations = Some(number()?),
+                "simulations" => budget.simulations = Some(number()?),
+                "treesize" => budget.max_nodes = Some(number()? as usize),
+                "infinite" => (),
+                _ => return Err(format!("unknown go argument: {arg}")),
+            }
+        }
+        if let BoardState::Concluded(_) = self.state.board_state {
+            println!("bestmove none");
+            return Ok(());
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let state = self.state.clone();
+        let config = self.config;
+        let mut rng = self.seed.map_or_else(SmallRng::from_entropy, SmallRng::seed_from_u64);
+        let mut root = self.root.take().unwrap_or_default();
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let mut last_info = start;
+            let mut last_progress = SearchProgress::default();
+            let best_move = mcts_until(&state, &config, &mut root, &mut rng, |root, progress| {
+                last_progress = progress;
+                if last_info.elapsed() > INFO_INTERVAL {
+                    print_info(root, &config, start, progress);
+                    last_info = Instant::now();
+                }
+                stop_clone.load(Ordering::Relaxed) || budget.is_exhausted(start.elapsed(), progress)
+            }, |_, progress| stop_clone.load(Ordering::Relaxed) || budget.is_hard_limit_reached(start.elapsed(), progress));
+            print_info(&root, &config, start, last_progress);
+            // without a limit the search only ends on stop, even once it has proven the result
+            if budget.is_unlimited() {
+                while !stop_clone.load(Ordering::Relaxed) {
+                    thread::sleep(STOP_POLL_INTERVAL);
+                }
+            }
+            println!("bestmove {best_move}");
+            root
+        });
+        self.search = Some((stop, handle));
+        Ok(())
+    }
+}
+
+fn main() {
+    let mut engine = Engine::new();
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let args = line.split_whitespace().collect::<Vec<_>>();
+        let Some((&command, args)) = args.split_first() else {
+            continue;
+        };
+        // searches finish on their own once a limit is hit, so reap them before anything else
+        if engine.search.as_ref().is_some_and(|(_, handle)| handle.is_finished()) {
+            engine.finish_search();
+        }
+        let result = match command {
+            "uti" => {
+                println!("id name ultimate-tic-tac-toe {}", env!("CARGO_PKG_VERSION"));
+                println!("id author {}", env!("CARGO_PKG_AUTHORS"));
+                let config = MctsConfig::default();
+                println!("option name RandomCount type spin default {} min 1 max 10000", config.rollouts);
+                println!("option name Exploration type string default {}", config.exploration);
+                println!("option name ExpansionThreshold type spin default {} min 1 max 100000", config.expansion_threshold);
+                println!("option name FinalPolicy type combo default {} var average var visits var secure var robust", config.final_policy);
+                println!("option name FirstPlayUrgency type string default none");
+                println!("option name RaveEquivalence type string default {}", config.rave_equivalence);
+                println!("option name Threads type spin default {} min 1 max 256", config.threads);
+                println!("option name Seed type string default random");
+                println!("utiok");
+                Ok(())
+            },
+            "isready" => {
+                println!("readyok");
+                Ok(())
+            },
+            "stop" => {
+                engine.finish_search();
+                Ok(())
+            },
+            "quit" => break,
+            "d" => {
+                println!("{}", engine.state);
+                println!("fen {}", engine.state.to_fen());
+                Ok(())
+            },
+            command => {
+                // the rest of the commands change what a running search would be working on
+                engine.finish_search();
+                match command {
+                    "newgame" => {
+                        engine.root = Some(Node::new());
+                        Ok(())
+                    },
+                    "setoption" => engine.set_option(args),
+                    "position" => engine.set_position(args),
+                    "go" => engine.go(args),
+                    _ => Err(format!("unknown command: {command}")),
+                }
+            },
+        };
+        if let Err(error) = result {
+            println!("info string {error}");
+        }
+    }
+    engine.finish_search();
+}
@@ -0,0 +1,383 @@
+use std::{env, fmt, process, str::FromStr, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Mutex}, thread};
+
+use ultimate_tic_tac_toe::{ai::{agent::{Agent, GreedyAgent, MctsAgent, MinimaxAgent, RandomAgent, SearchBudget}, mcts::MctsConfig}, game::{BoardResult, BoardState, GameState, Player, Ruleset}};
+use web_time::{Duration, Instant};
+
+const USAGE: &str = "usage: uttt-tournament [--games N] [--threads N] [--seed N] [--sprt ELO0 ELO1] AGENT AGENT [AGENT...]
+
+plays every pair of agents against each other N times, swapping who starts every game
+
+agents:
+  mcts:LIMIT[:RANDOM_COUNT][:NAME=VALUE...]
+                           mcts with RANDOM_COUNT rollouts per leaf (default 10), stopping each move after LIMIT,
+                           which is milliseconds like 500 or 500ms, iterations like 2000it or simulations like 20000sims.
+                           the other settings are changed with c, expansion, policy (average, visits, secure or
+                           robust), fpu, rave and threads, like mcts:500it:c=0.8:fpu=1. threads searches each move
+                           on that many threads, on top of the games that --threads runs at once
+  minimax:DEPTH            minimax searching DEPTH moves ahead, with random rollouts at the leaves
+  random                   uniformly random moves
+  greedy                   takes mini boards when it can, and avoids giving them away
+
+--seed makes every game replayable when the agents use iteration or simulation limits
+
+--sprt stops early once a sequential probability ratio test between exactly two agents decides whether the
+first is ELO0 or ELO1 elo stronger than the second, at 5% error rates";
+
+#[derive(Clone)]
+enum AgentSpec {
+    Mcts { budget: SearchBudget, config: MctsConfig, options: Vec<String> },
+    Minimax { depth: u32 },
+    Random,
+    Greedy,
+}
+
+impl fmt::Display for AgentSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgentSpec::Mcts { budget, config, options } => {
+                match budget {
+                    SearchBudget { thinking_time: Some(thinking_time), .. } => write!(f, "mcts:{}ms", thinking_time.as_millis())?,
+                    SearchBudget { iterations: Some(iterations), .. } => write!(f, "mcts:{iterations}it")?,
+                    SearchBudget { simulations: Some(simulations), .. } => write!(f, "mcts:{simulations}sims")?,
+                    _ => write!(f, "mcts")?,
+                }
+                write!(f, ":{}", config.rollouts)?;
+                for option in options {
+                    write!(f, ":{option}")?;
+                }
+                Ok(())
+            },
+            AgentSpec::Minimax { depth } => write!(f, "minimax:{depth}"),
+            AgentSpec::Random => write!(f, "random"),
+            AgentSpec::Greedy => write!(f, "greedy"),
+        }
+    }
+}
+
+impl AgentSpec {
+    fn build(&self) -> (Box<dyn Agent>, SearchBudget) {
+        match *self {
+            AgentSpec::Mcts { budget, config, .. } => (Box::new(MctsAgent::new(config)), budget),
+            AgentSpec::Minimax { depth } => (Box::new(MinimaxAgent::new(depth, 10)), SearchBudget::default()),
+            AgentSpec::Random => (Box::new(RandomAgent::default()), SearchBudget::default()),
+            AgentSpec::Greedy => (Box::new(GreedyAgent::default()), SearchBudget::default()),
+        }
+    }
+}
+
+fn parse_agent(spec: &str) -> Result<AgentSpec, String> {
+    let parts = spec.split(':').collect::<Vec<_>>();
+    let number = |i: usize, default: Option<u32>| -> Result<u32, String> {
+        match (parts.get(i), default) {
+            (Some(part), _) => part.parse().map_err(|_| format!("invalid number in agent {spec}: {part}")),
+            (None, Some(default)) => Ok(default),
+            (None, None) => Err(format!("missing number in agent {spec}")),
+        }
+    };
+    match parts[0] {
+        "mcts" if parts.len() >= 2 => {
+            let limit = parts[1];
+            let budget = if let Some(iterations) = limit.strip_suffix("it") {
+                SearchBudget::iterations(iterations.parse().map_err(|_| format!("invalid iterations in agent {spec}"))?)
+            } else if let Some(simulations) = limit.strip_suffix("sims") {
+                SearchBudget { simulations: Some(simulations.parse().map_err(|_| format!("invalid simulations in agent {spec}"))?), ..SearchBudget::default() }
+            } else {
+                let milliseconds = limit.strip_suffix("ms").unwrap_or(limit);
+                SearchBudget::time(Duration::from_millis(milliseconds.parse().map_err(|_| format!("invalid thinking time in agent {spec}"))?))
+            };
+            let (options, rest) = parts[2..].iter().partition::<Vec<&str>, _>(|part| part.contains('='));
+            if rest.len() > 1 {
+                return Err(format!("invalid agent: {spec}"));
+            }
+            let mut config = MctsConfig::default();
+            if !rest.is_empty() {
+                config.set("rollouts", rest[0]).map_err(|error| format!("{error} in agent {spec}"))?;
+            }
+            for option in &options {
+                let (name, value) = option.split_once('=').unwrap();
+                config.set(name, value).map_err(|error| format!("{error} in agent {spec}"))?;
+            }
+            Ok(AgentSpec::Mcts { budget, config, options: options.into_iter().map(str::to_string).collect() })
+        },
+        "minimax" if parts.len() == 2 => Ok(AgentSpec::Minimax { depth: number(1, None)? }),
+        "random" if parts.len() == 1 => Ok(AgentSpec::Random),
+        "greedy" if parts.len() == 1 => Ok(AgentSpec::Greedy),
+        _ => Err(format!("invalid agent: {spec}")),
+    }
+}
+
+// plays one game and returns the result from the first agent's point of view
+fn play_game(first: &AgentSpec, second: &AgentSpec, first_is_x: bool, seed: Option<u64>) -> GameResult {
+    let mut state = GameState::new(Player::X, Ruleset::default());
+    let (mut x, mut o) = if first_is_x { (first.build(), second.build()) } else { (second.build(), first.build()) };
+    if let Some(seed) = seed {
+        x.0.seed(seed);
+        o.0.seed(seed ^ 1);
+    }
+    while let BoardState::Ongoing = state.board_state {
+        let position = match state.turn {
+            Player::X => x.0.choose_move(&state, x.1),
+            Player::O => o.0.choose_move(&state, o.1),
+        };
+        state.play(position).expect("an agent chose an illegal move");
+        x.0.notify_move(position);
+        o.0.notify_move(position);
+    }
+    match (state.board_state, first_is_x) {
+        (BoardState::Concluded(BoardResult::Tie), _) => GameResult::Draw,
+        (BoardState::Concluded(BoardResult::XWin), true) | (BoardState::Concluded(BoardResult::OWin), false) => GameResult::Win,
+        _ => GameResult::Loss,
+    }
+}
+
+#[derive(Copy, Clone)]
+enum GameResult {
+    Win,
+    Draw,
+    Loss,
+}
+
+#[derive(Copy, Clone, Default)]
+struct Score {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+impl Score {
+    fn add(&mut self, result: GameResult) {
+        match result {
+            GameResult::Win => self.wins += 1,
+            GameResult::Draw => self.draws += 1,
+            GameResult::Loss => self.losses += 1,
+        }
+    }
+    fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+    // the mean score per game and its variance per game
+    fn mean_and_variance(&self) -> (f64, f64) {
+        let games = self.games() as f64;
+        let mean = (self.wins as f64 + self.draws as f64 / 2.0) / games;
+        let variance = (self.wins as f64 * (1.0 - mean).powi(2) + self.draws as f64 * (0.5 - mean).powi(2) + self.losses as f64 * mean.powi(2)) / games;
+        (mean, variance)
+    }
+    // elo difference with a 95% confidence interval, unbounded while every game has gone the same way
+    fn elo(&self) -> (f64, f64, f64) {
+        let (mean, variance) = self.mean_and_variance();
+        if variance == 0.0 {
+            return (elo(mean), f64::NEG_INFINITY, f64::INFINITY);
+        }
+        let margin = 1.96 * (variance / self.games() as f64).sqrt();
+        (elo(mean), elo(mean - margin), elo(mean + margin))
+    }
+    // log likelihood ratio of elo1 against elo0, using the normal approximation to the score distribution
+    fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        if self.games() == 0 {
+            return 0.0;
+        }
+        let (mean, variance) = self.mean_and_variance();
+        let (score0, score1) = (expected_score(elo0), expected_score(elo1));
+        // until both sides have won a game the variance is underestimated, and one lucky streak could end the
+        // test, so it is taken to be at least that of a draw-free match at the hypothesised scores
+        let variance = if self.wins == 0 || self.losses == 0 {
+            variance.max((score0 * (1.0 - score0)).max(score1 * (1.0 - score1)))
+        } else {
+            variance
+        };
+        self.games() as f64 * (score1 - score0) * (2.0 * mean - score0 - score1) / (2.0 * variance)
+    }
+}
+
+fn elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score.clamp(1e-6, 1.0 - 1e-6) - 1.0).log10()
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+struct Options {
+    games: usize,
+    threads: usize,
+    sprt: Option<(f64, f64)>,
+    seed: Option<u64>,
+    agents: Vec<AgentSpec>,
+}
+
+fn next_value<T: FromStr>(args: &mut impl Iterator<Item = String>, name: &str) -> Result<T, String> {
+    let value = args.next().ok_or(format!("missing {name}"))?;
+    value.parse().map_err(|_| format!("invalid {name}: {value}"))
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        games: 100,
+        threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+        sprt: None,
+        seed: None,
+        agents: Vec::new(),
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--games" => options.games = next_value(&mut args, "game count")?,
+            "--threads" => options.threads = next_value::<usize>(&mut args, "thread count")?.max(1),
+            "--seed" => options.seed = Some(next_value(&mut args, "seed")?),
+            "--sprt" => options.sprt = Some((next_value(&mut args, "elo0")?, next_value(&mut args, "elo1")?)),
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                process::exit(0);
+            },
+            spec => options.agents.push(parse_agent(spec)?),
+        }
+    }
+    if options.agents.len() < 2 {
+        return Err("need at least two agents".to_string());
+    }
+    if options.sprt.is_some() && options.agents.len() != 2 {
+        return Err("--sprt needs exactly two agents".to_string());
+    }
+    Ok(options)
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{error}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    let agents = &options.agents;
+    let mut pairs = Vec::new();
+    for first in 0..agents.len() {
+        for second in first + 1..agents.len() {
+            pairs.push((first, second));
+        }
+    }
+    let jobs = pairs.iter().flat_map(|&pair| (0..options.games).map(move |game| (pair, game))).collect::<Vec<_>>();
+    let next_job = AtomicUsize::new(0);
+    let finished = AtomicBool::new(false);
+    let scores = Mutex::new(vec![vec![Score::default(); agents.len()]; agents.len()]);
+    let start = Instant::now();
+
+    thread::scope(|s| {
+        for _ in 0..options.threads {
+            s.spawn(|| {
+                while !finished.load(Ordering::Relaxed) {
+                    let Some(&((first, second), game)) = jobs.get(next_job.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    // a different seed for every game, but the same one for a game whenever the tournament is rerun
+                    let seed = options.seed.map(|seed| seed.wrapping_add(((first * agents.len() + second) * options.games + game) as u64 * 2));
+                    let result = play_game(&agents[first], &agents[second], game % 2 == 0, seed);
+                    let mut scores = scores.lock().unwrap();
+                    scores[first][second].add(result);
+                    scores[second][first].add(match result {
+                        GameResult::Win => GameResult::Loss,
+                        GameResult::Draw => GameResult::Draw,
+                        GameResult::Loss => GameResult::Win,
+                    });
+                    let score = scores[first][second];
+                    print!("{} - {}: +{} ={} -{}", agents[first], agents[second], score.wins, score.draws, score.losses);
+                    if let Some((elo0, elo1)) = options.sprt {
+                        let llr = score.llr(elo0, elo1);
+                        // bounds for 5% false positive and false negative rates
+                        let bound = (0.95f64 / 0.05).ln();
+                        print!(", llr {llr:.2} ({:.2}, {:.2})", -bound, bound);
+                        if llr.abs() >= bound {
+                            finished.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    println!();
+                }
+            });
+        }
+    });
+
+    let scores = scores.into_inner().unwrap();
+    println!();
+    println!("{} games in {:.1?}", scores.iter().flatten().map(Score::games).sum::<u32>() / 2, start.elapsed());
+    println!();
+    let width = agents.iter().map(|agent| agent.to_string().len()).max().unwrap_or(0).max(20);
+    println!("{:<width$} {:>8} {:>8} {:>8} {:>8}   elo (95% ci)", "agent", "wins", "draws", "losses", "score");
+    for (i, agent) in agents.iter().enumerate() {
+        let mut total = Score::default();
+        for score in &scores[i] {
+            total.wins += score.wins;
+            total.draws += score.draws;
+            total.losses += score.losses;
+        }
+        if total.games() == 0 {
+            continue;
+        }
+        let (mean, _) = total.mean_and_variance();
+        let (elo, low, high) = total.elo();
+        println!("{:<width$} {:>8} {:>8} {:>8} {:>7.1}%   {:+.0} ({:+.0}, {:+.0})", agent.to_string(), total.wins, total.draws, total.losses, mean * 100.0, elo, low, high);
+    }
+    if pairs.len() > 1 {
+        println!();
+        for &(first, second) in &pairs {
+            let score = scores[first][second];
+            if score.games() == 0 {
+                continue;
+            }
+            let (elo, low, high) = score.elo();
+            println!("{} - {}: +{} ={} -{}, elo {:+.0} ({:+.0}, {:+.0})", agents[first], agents[second], score.wins, score.draws, score.losses, elo, low, high);
+        }
+    }
+    if let Some((elo0, elo1)) = options.sprt {
+        let llr = scores[0][1].llr(elo0, elo1);
+        let bound = (0.95f64 / 0.05).ln();
+        println!();
+        println!("sprt elo0 {elo0} elo1 {elo1}: llr {llr:.2}, {}", if llr >= bound {
+            "accept elo1"
+        } else if llr <= -bound {
+            "accept elo0"
+        } else {
+            "inconclusive"
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn few_decisive_games_do_not_end_sprt() {
+        let bound = (0.95f64 / 0.05).ln();
+        for (elo0, elo1) in [(0.0, 50.0), (0.0, 400.0), (-50.0, 0.0)] {
+            for result in [GameResult::Win, GameResult::Loss] {
+                let mut score = Score::default();
+                for _ in 0..2 {
+                    score.add(result);
+                    assert!(score.llr(elo0, elo1).abs() < bound, "{} games ended sprt {elo0} {elo1}", score.games());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn draws_count_once_both_sides_have_won() {
+        let score = Score { wins: 30, draws: 40, losses: 10 };
+        let (mean, variance) = score.mean_and_variance();
+        let (score0, score1) = (expected_score(0.0), expected_score(50.0));
+        // the draws bring the variance under the floor used for one-sided runs
+        assert!(variance < score0 * (1.0 - score0));
+        let llr = 80.0 * (score1 - score0) * (2.0 * mean - score0 - score1) / (2.0 * variance);
+        assert!((score.llr(0.0, 50.0) - llr).abs() < 1e-9);
+    }
+
+    #[test]
+    fn one_sided_results_have_unbounded_elo_interval() {
+        let mut score = Score::default();
+        score.add(GameResult::Win);
+        let (_, low, high) = score.elo();
+        assert_eq!((low, high), (f64::NEG_INFINITY, f64::INFINITY));
+        score.add(GameResult::Loss);
+        let (elo, low, high) = score.elo();
+        assert!(low.is_finite() && high.is_finite() && low < elo && elo < high);
+    }
+}
@@ -3,10 +3,21 @@ use std::{borrow::BorrowMut, collections::HashSet, default, fmt::Debug, mem, rc:
 use nohash_hasher::BuildNoHashHasher;
 use std::hash::Hash;
 use std::hash::Hasher;
-use rand::{seq::IteratorRandom, thread_rng};
+use std::cmp::Ordering;
+use rand::{seq::IteratorRandom, Rng};
 use serde::{Deserialize, Serialize};
+use web_time::SystemTime;
 
 use crate::ai::{mcts::{mcts, Node}, minimax_expected_outcome};
+
+mod notation;
+mod perft;
+mod record;
+mod symmetry;
+mod zobrist;
+pub use notation::NotationError;
+pub use record::RecordError;
+pub use symmetry::Symmetry;
 #[derive(Copy, Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct Position(pub (usize, usize), pub (usize, usize));
 impl nohash_hasher::IsEnabled for Position {}
@@ -16,78 +27,284 @@ impl Hash for Position {
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+// equality compares every field, while hashing only uses the zobrist key, which equal states share
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GameState {
-    pub mini_boards: [[Board; 3]; 3],
-    pub meta_board: [[BoardState; 3]; 3],
+    mini_boards: [Board; 9],
+    meta: Board,
+    ties: u16,
+    ruleset: Ruleset,
+    hash: u64,
     pub next_meta_move: Option<(usize, usize)>,
     pub board_state: BoardState,
     pub turn: Player,
-    empty_spaces : Vec<Position>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ruleset {
+    pub sent_to_finished: SentToFinished,
+    pub tied_boards_count_for_both: bool,
+    pub finished_boards_playable: bool,
+    pub meta_draw: MetaDraw,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SentToFinished {
+    #[default]
+    OpenBoards,
+    Anywhere,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MetaDraw {
+    #[default]
+    Tie,
+    MostBoards,
 }
 #[derive(Clone)]
 pub struct Game {
     pub state: GameState,
     pub x: PlayerType,
     pub o: PlayerType,
+    pub thinking_time: Option<Duration>,
+    pub started: SystemTime,
+    initial: GameState,
+    history: Vec<MoveRecord>,
+}
+#[derive(Clone)]
+pub struct MoveRecord {
+    pub position: Position,
+    pub player: Player,
+    pub timestamp: SystemTime,
+    pub comment: Option<String>,
+    pub eval: Option<f32>,
+    token: MoveToken,
 }
 impl Game {
     pub fn new(starting_player: Player, x: PlayerType, o: PlayerType) -> Self {
-        let mut game = Game {
-            state: GameState {
-            mini_boards: [[Board::new(), Board::new(), Board::new()], [Board::new(), Board::new(), Board::new()], [Board::new(), Board::new(), Board::new()]],
-            meta_board: [[BoardState::Ongoing; 3]; 3],
-            next_meta_move: None,
-            turn: starting_player,
-            board_state: BoardState::Ongoing,
-            empty_spaces: ALL_SPACES.to_vec(),
-            },
+        Game::from_state(GameState::new(starting_player, Ruleset::default()), x, o)
+    }
+    pub fn from_state(state: GameState, x: PlayerType, o: PlayerType) -> Self {
+        Game {
+            initial: state.clone(),
+            state,
             x,
             o,
-        };
-        game
+            thinking_time: None,
+            started: SystemTime::now(),
+            history: Vec::new(),
+        }
+    }
+    pub fn place(&mut self, position: Position) -> Result<BoardState, InvalidMoveError> {
+        let player = self.state.turn;
+        let token = self.state.play(position)?;
+        self.history.push(MoveRecord {
+            position,
+            player,
+            timestamp: SystemTime::now(),
+            comment: None,
+            eval: None,
+            token,
+        });
+        Ok(token.result)
+    }
+    pub fn annotate(&mut self, ply: usize, comment: Option<String>, eval: Option<f32>) {
+        if let Some(record) = self.history.get_mut(ply) {
+            record.comment = comment;
+            record.eval = eval;
+        }
+    }
+    pub fn undo(&mut self) -> Option<MoveRecord> {
+        let record = self.history.pop()?;
+        self.state.unplace(record.token);
+        Some(record)
+    }
+    pub fn rewind(&mut self, ply: usize) {
+        while self.ply() > ply {
+            self.undo();
+        }
+    }
+    pub fn history(&self) -> &[MoveRecord] {
+        &self.history
+    }
+    pub fn ply(&self) -> usize {
+        self.history.len()
+    }
+    pub fn initial_state(&self) -> &GameState {
+        &self.initial
+    }
+    pub fn state_at(&self, ply: usize) -> Option<GameState> {
+        let mut state = self.initial.clone();
+        for record in self.history.get(..ply)? {
+            state.place(record.position.0, record.position.1).expect("history only contains moves that were valid when played");
+        }
+        Some(state)
     }
 }
 
-const ALL_SPACES: [Position; 81] = {
-    let mut pairs = [(0, 0); 9];
-    let mut i = 0;
-    let mut j = 0;
-    let mut result = [Position((0, 0), (0, 0)); 81];
-    while i < 3 {
-        j = 0;
-        while j < 3 {
-            pairs[i*3+j] = (i, j);
-            j += 1
-        }
-        i += 1
-    }
-    i = 0;
-    while i < 9 {
-        let mut j = 0;
-        while j < 9 {
-            result[i*9+j] = Position(pairs[i], pairs[j]);
-            j += 1;
-        }
-        i += 1;
-    }
-    result
+impl Position {
+    pub fn index(self) -> usize {
+        (self.0.1 * 3 + self.0.0) * 9 + self.1.1 * 3 + self.1.0
+    }
+    pub fn from_index(index: usize) -> Position {
+        let (meta, mini) = (index / 9, index % 9);
+        Position((meta % 3, meta / 3), (mini % 3, mini / 3))
+    }
+    pub fn grid(self) -> (usize, usize) {
+        (self.0.0 * 3 + self.1.0, self.0.1 * 3 + self.1.1)
+    }
+    pub fn from_grid(x: usize, y: usize) -> Position {
+        Position((x / 3, y / 3), (x % 3, y % 3))
+    }
+}
+
+const FULL_BOARD: u16 = 0b111_111_111;
+
+const WIN_LINES: [u16; 8] = [
+    0b000_000_111,
+    0b000_111_000,
+    0b111_000_000,
+    0b001_001_001,
+    0b010_010_010,
+    0b100_100_100,
+    0b100_010_001,
+    0b001_010_100,
+];
+
+const WINNING: [bool; 512] = {
+    let mut table = [false; 512];
+    let mut mask = 0;
+    while mask < 512 {
+        let mut i = 0;
+        while i < 8 {
+            if mask & WIN_LINES[i] as usize == WIN_LINES[i] as usize {
+                table[mask] = true;
+            }
+            i += 1;
+        }
+        mask += 1;
+    }
+    table
 };
 
+#[derive(Copy, Clone)]
+pub struct Moves(pub u128);
+
+impl Iterator for Moves {
+    type Item = Position;
+    fn next(&mut self) -> Option<Position> {
+        if self.0 == 0 {
+            return None
+        }
+        let index = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1;
+        Some(Position::from_index(index))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let count = self.0.count_ones() as usize;
+        (count, Some(count))
+    }
+    fn nth(&mut self, n: usize) -> Option<Position> {
+        for _ in 0..n {
+            if self.0 == 0 {
+                return None
+            }
+            self.0 &= self.0 - 1;
+        }
+        self.next()
+    }
+}
+impl ExactSizeIterator for Moves {}
 
 impl GameState {
-    pub fn new(starting_player: Player) -> Self {
+    pub fn new(starting_player: Player, ruleset: Ruleset) -> Self {
         GameState {
-            mini_boards: [[Board::new(), Board::new(), Board::new()], [Board::new(), Board::new(), Board::new()], [Board::new(), Board::new(), Board::new()]],
-            meta_board: [[BoardState::Ongoing; 3]; 3],
+            mini_boards: [Board::new(); 9],
+            meta: Board::new(),
+            ties: 0,
+            ruleset,
+            hash: match starting_player {
+                Player::X => 0,
+                Player::O => zobrist::O_TO_MOVE,
+            },
             next_meta_move: None,
             turn: starting_player,
             board_state: BoardState::Ongoing,
-            empty_spaces: ALL_SPACES.to_vec(),
         }
     }
 
-    
+    pub fn mini_board(&self, meta_pos: (usize, usize)) -> Board {
+        self.mini_boards[meta_pos.1 * 3 + meta_pos.0]
+    }
+
+    pub fn mini_board_state(&self, meta_pos: (usize, usize)) -> BoardState {
+        let bit = 1 << (meta_pos.1 * 3 + meta_pos.0);
+        if self.meta.x & bit != 0 {
+            BoardState::Concluded(BoardResult::XWin)
+        } else if self.meta.o & bit != 0 {
+            BoardState::Concluded(BoardResult::OWin)
+        } else if self.ties & bit != 0 {
+            BoardState::Concluded(BoardResult::Tie)
+        } else {
+            BoardState::Ongoing
+        }
+    }
+
+    pub fn meta_board(&self) -> [[BoardState; 3]; 3] {
+        std::array::from_fn(|y| std::array::from_fn(|x| self.mini_board_state((x, y))))
+    }
+
+    pub fn get_space(&self, pos: Position) -> BoardSpace {
+        self.mini_board(pos.0).get_space(pos.1)
+    }
+
+    pub fn ruleset(&self) -> Ruleset {
+        self.ruleset
+    }
+
+    pub fn zobrist(&self) -> u64 {
+        self.hash
+    }
+
+    fn compute_zobrist(&self) -> u64 {
+        let mut hash = zobrist::forced(self.next_meta_move);
+        if let Player::O = self.turn {
+            hash ^= zobrist::O_TO_MOVE;
+        }
+        for index in 0..81 {
+            if let BoardSpace::Taken(player) = self.get_space(Position::from_index(index)) {
+                hash ^= zobrist::cell(player.index(), index);
+            }
+        }
+        hash
+    }
+
+    fn concluded_boards(&self) -> u16 {
+        self.meta.x | self.meta.o | self.ties
+    }
+
+    fn free_boards(&self) -> u16 {
+        let mut boards = 0;
+        for (i, board) in self.mini_boards.iter().enumerate() {
+            if board.empty_spaces() != 0 {
+                boards |= 1 << i;
+            }
+        }
+        if self.ruleset.finished_boards_playable || self.ruleset.sent_to_finished == SentToFinished::Anywhere {
+            boards
+        } else {
+            boards & !self.concluded_boards()
+        }
+    }
+
+    fn can_send_to(&self, meta_index: usize) -> bool {
+        self.mini_boards[meta_index].empty_spaces() != 0
+            && (self.ruleset.finished_boards_playable || self.concluded_boards() & (1 << meta_index) == 0)
+    }
+
+    pub fn is_board_playable(&self, meta_pos: (usize, usize)) -> bool {
+        self.move_mask() >> ((meta_pos.1 * 3 + meta_pos.0) * 9) & FULL_BOARD as u128 != 0
+    }
 
     pub fn get_icon(&self, meta_pos: (usize, usize), mini_pos: (usize, usize)) -> &'static str {
         const BIG_X: [[&str; 3]; 3] = [
@@ -100,9 +317,9 @@ impl GameState {
             ["|", "O", "|"],
             ["\\", "-", "/"],
             ];
-        match self.meta_board[meta_pos.1][meta_pos.0] {
+        match self.mini_board_state(meta_pos) {
             BoardState::Ongoing | BoardState::Concluded(BoardResult::Tie) => {
-                match self.mini_boards[meta_pos.1][meta_pos.0].get_space(mini_pos) {
+                match self.mini_board(meta_pos).get_space(mini_pos) {
                     BoardSpace::Empty => " ",
                     BoardSpace::Taken(Player::X) => "X",
                     BoardSpace::Taken(Player::O) => "O"
@@ -116,72 +333,195 @@ impl GameState {
 
         }
     }
-    pub fn check_wins(&self, pos: (usize, usize)) -> BoardState {
-            let mut row = 0;
-            let mut col = 0;
-            let mut diag = 0;
-            let mut rdiag = 0;
-            for i in 0..3 {
-                row += match &self.meta_board[pos.1][i] { BoardState::Ongoing => 0, BoardState::Concluded(x) => *x as i32};
-                col += match &self.meta_board[i][pos.0] { BoardState::Ongoing => 0, BoardState::Concluded(x) => *x as i32};
-                diag += match &self.meta_board[i][i] { BoardState::Ongoing => 0, BoardState::Concluded(x) => *x as i32};
-                rdiag += match &self.meta_board[i][2-i] { BoardState::Ongoing => 0, BoardState::Concluded(x) => *x as i32};
-            }
-            if row == 3 || col == 3 || diag == 3 || rdiag == 3 {
-                return BoardState::Concluded(BoardResult::XWin)
-            }
-            if row == -3 || col == -3 || diag == -3 || rdiag == -3 {
-                return BoardState::Concluded(BoardResult::OWin)
-            }
-            if self.meta_board.iter().flatten().all(|x| matches!(x, BoardState::Concluded(_))) {
-                return BoardState::Concluded(BoardResult::Tie)
+    fn meta_lines(&self) -> (bool, bool) {
+        let (x, o) = if self.ruleset.tied_boards_count_for_both {
+            (self.meta.x | self.ties, self.meta.o | self.ties)
+        } else {
+            (self.meta.x, self.meta.o)
+        };
+        (WINNING[x as usize], WINNING[o as usize])
+    }
+
+    pub fn mini_boards_won(&self) -> (u32, u32) {
+        (self.meta.x.count_ones(), self.meta.o.count_ones())
+    }
+
+    pub fn won_on_boards(&self) -> bool {
+        matches!(self.board_state, BoardState::Concluded(BoardResult::XWin | BoardResult::OWin)) && self.meta_lines() == (false, false)
+    }
+
+    pub fn check_wins(&self) -> BoardState {
+        match self.meta_lines() {
+            // a tied board can complete a line for both players at once, the player who tied it takes the game
+            (true, true) => return BoardState::Concluded(BoardResult::from(self.turn.switch())),
+            (true, false) => return BoardState::Concluded(BoardResult::XWin),
+            (false, true) => return BoardState::Concluded(BoardResult::OWin),
+            (false, false) => (),
+        }
+        if self.concluded_boards() == FULL_BOARD {
+            if let MetaDraw::MostBoards = self.ruleset.meta_draw {
+                let (x, o) = self.mini_boards_won();
+                match x.cmp(&o) {
+                    Ordering::Greater => return BoardState::Concluded(BoardResult::XWin),
+                    Ordering::Less => return BoardState::Concluded(BoardResult::OWin),
+                    Ordering::Equal => (),
+                }
             }
+            return BoardState::Concluded(BoardResult::Tie)
+        }
 
-            BoardState::Ongoing
+        BoardState::Ongoing
     }
     pub fn place(&mut self, meta_pos: (usize, usize), mini_pos: (usize, usize)) -> Result<BoardState, InvalidMoveError> {
-        if let Some(next_meta_move) = self.next_meta_move {
-            if next_meta_move != meta_pos {
-                return Err(InvalidMoveError)
-            }
+        let position = Position(meta_pos, mini_pos);
+        if meta_pos.0 > 2 || meta_pos.1 > 2 || mini_pos.0 > 2 || mini_pos.1 > 2 {
+            return Err(InvalidMoveError::OutOfRange(position))
         }
-        if let BoardState::Concluded(_) = self.meta_board[meta_pos.1][meta_pos.0] {
-            return Err(InvalidMoveError)
+        if let BoardState::Concluded(_) = self.board_state {
+            return Err(InvalidMoveError::GameOver(position))
         }
+        let meta_bit = 1 << (meta_pos.1 * 3 + meta_pos.0);
+        match self.next_meta_move {
+            Some(next_meta_move) if next_meta_move != meta_pos => {
+                return Err(InvalidMoveError::WrongMiniBoard { position, expected: next_meta_move })
+            }
+            Some(_) => (),
+            None if self.free_boards() & meta_bit == 0 => return Err(InvalidMoveError::MiniBoardConcluded(position)),
+            None => (),
+        }
+        let was_concluded = self.concluded_boards() & meta_bit != 0;
 
-
-        let mini_result = self.mini_boards[meta_pos.1][meta_pos.0].place(mini_pos, self.turn)?;
+        let mini_result = self.mini_boards[meta_pos.1 * 3 + meta_pos.0].place(position, self.turn)?;
+        self.hash ^= zobrist::cell(self.turn.index(), position.index()) ^ zobrist::O_TO_MOVE ^ zobrist::forced(self.next_meta_move);
         self.turn = self.turn.switch();
-        self.meta_board[meta_pos.1][meta_pos.0] = mini_result;
-        self.empty_spaces.remove(self.empty_spaces.iter().position(|x| *x == Position(meta_pos, mini_pos)).expect("move not found in empty spaces"));
+        match mini_result {
+            _ if was_concluded => (),
+            BoardState::Ongoing => (),
+            BoardState::Concluded(BoardResult::XWin) => self.meta.x |= meta_bit,
+            BoardState::Concluded(BoardResult::OWin) => self.meta.o |= meta_bit,
+            BoardState::Concluded(BoardResult::Tie) => self.ties |= meta_bit,
+        }
 
-        if let BoardState::Ongoing = self.meta_board[mini_pos.1][mini_pos.0] {
+        if self.can_send_to(mini_pos.1 * 3 + mini_pos.0) {
             self.next_meta_move = Some(mini_pos);
         } else {
             self.next_meta_move = None;
         }
-        
-        if let BoardState::Concluded(result) = mini_result {
-            self.empty_spaces.retain(|Position(meta, _mini)| meta != &meta_pos);
-            self.board_state = self.check_wins(meta_pos);
+        self.hash ^= zobrist::forced(self.next_meta_move);
+
+        if let (false, BoardState::Concluded(_)) = (was_concluded, mini_result) {
+            self.board_state = self.check_wins();
             return Ok(self.board_state)
         }
 
         Ok(BoardState::Ongoing)
     }
 
+    pub fn play(&mut self, position: Position) -> Result<MoveToken, InvalidMoveError> {
+        let next_meta_move = self.next_meta_move;
+        let board_state = self.board_state;
+        let concluded_boards = self.concluded_boards();
+        let result = self.place(position.0, position.1)?;
+        Ok(MoveToken {
+            position,
+            result,
+            next_meta_move,
+            board_state,
+            concluded_board: self.concluded_boards() != concluded_boards,
+        })
+    }
+
+    pub fn unplace(&mut self, token: MoveToken) {
+        let Position(meta_pos, mini_pos) = token.position;
+        let meta_bit = 1 << (meta_pos.1 * 3 + meta_pos.0);
+        self.mini_boards[meta_pos.1 * 3 + meta_pos.0].remove(mini_pos);
+        if token.concluded_board {
+            self.meta.x &= !meta_bit;
+            self.meta.o &= !meta_bit;
+            self.ties &= !meta_bit;
+        }
+        self.turn = self.turn.switch();
+        self.hash ^= zobrist::cell(self.turn.index(), token.position.index()) ^ zobrist::O_TO_MOVE
+            ^ zobrist::forced(self.next_meta_move) ^ zobrist::forced(token.next_meta_move);
+        self.next_meta_move = token.next_meta_move;
+        self.board_state = token.board_state;
+    }
+
+    pub fn move_mask(&self) -> u128 {
+        if let BoardState::Concluded(_) = self.board_state {
+            return 0
+        }
+        let boards = match self.next_meta_move {
+            Some(meta_move) => 1 << (meta_move.1 * 3 + meta_move.0),
+            None => self.free_boards(),
+        };
+        let mut mask = 0;
+        for (i, board) in self.mini_boards.iter().enumerate() {
+            if boards & (1 << i) != 0 {
+                mask |= (board.empty_spaces() as u128) << (i * 9);
+            }
+        }
+        mask
+    }
+
+    pub fn moves(&self) -> Moves {
+        Moves(self.move_mask())
+    }
+
     pub fn get_possible_moves(&self)  -> Vec<Position> {
-        if let Some(meta_move) = self.next_meta_move {
-            let moves = self.empty_spaces
-            .iter()
-            .filter(|Position(meta, _mini)| meta == &meta_move).map(|x| *x).collect();
-            return moves
+        self.moves().collect()
+    }
+}
+impl nohash_hasher::IsEnabled for GameState {}
+impl Hash for GameState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MoveToken {
+    pub position: Position,
+    pub result: BoardState,
+    next_meta_move: Option<(usize, usize)>,
+    board_state: BoardState,
+    concluded_board: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InvalidMoveError {
+    OutOfRange(Position),
+    WrongMiniBoard { position: Position, expected: (usize, usize) },
+    MiniBoardConcluded(Position),
+    SpaceTaken(Position),
+    GameOver(Position),
+}
+
+impl InvalidMoveError {
+    pub fn position(&self) -> Position {
+        match *self {
+            Self::OutOfRange(position)
+            | Self::WrongMiniBoard { position, .. }
+            | Self::MiniBoardConcluded(position)
+            | Self::SpaceTaken(position)
+            | Self::GameOver(position) => position,
         }
-        return self.empty_spaces.iter().map(|x| *x).collect();
     }
 }
-#[derive(Debug)]
-pub struct InvalidMoveError;
+
+impl fmt::Display for InvalidMoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange(Position(meta, mini)) => write!(f, "{:?} {:?} is outside the board", meta, mini),
+            Self::WrongMiniBoard { position, expected } => write!(f, "{position} is not in the mini-board that must be played, board {}", expected.1 * 3 + expected.0 + 1),
+            Self::MiniBoardConcluded(position) => write!(f, "{position} is in a mini-board that has already been decided"),
+            Self::SpaceTaken(position) => write!(f, "{position} is already taken"),
+            Self::GameOver(position) => write!(f, "cannot play {position}, the game is over"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidMoveError {}
 
 impl fmt::Display for GameState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -207,84 +547,89 @@ impl fmt::Display for GameState {
         Ok(())
     }
 }
-#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Board {
-    pub board: [[BoardSpace; 3]; 3],
-    x_count: u32,
-    o_count: u32,
+    x: u16,
+    o: u16,
 }
 
 
 impl Board {
     fn new() -> Self {
         Board {
-            board: [[BoardSpace::Empty; 3]; 3],
-            x_count: 0,
-            o_count: 0
+            x: 0,
+            o: 0
         }
     }
     pub fn get_space(&self, pos: (usize, usize)) -> BoardSpace {
-        self.board[pos.1][pos.0]
+        let bit = 1 << (pos.1 * 3 + pos.0);
+        if self.x & bit != 0 {
+            BoardSpace::Taken(Player::X)
+        } else if self.o & bit != 0 {
+            BoardSpace::Taken(Player::O)
+        } else {
+            BoardSpace::Empty
+        }
+    }
+    pub fn empty_spaces(&self) -> u16 {
+        FULL_BOARD & !(self.x | self.o)
     }
-    fn place(&mut self, pos: (usize, usize), player: Player) -> Result<BoardState, InvalidMoveError> {
-        if let BoardSpace::Empty = self.board[pos.1][pos.0] {
-            self.board[pos.1][pos.0] = BoardSpace::Taken(player);
+    fn place(&mut self, position: Position, player: Player) -> Result<BoardState, InvalidMoveError> {
+        let pos = position.1;
+        let bit = 1 << (pos.1 * 3 + pos.0);
+        if self.empty_spaces() & bit != 0 {
             match player {
-                Player::X => self.x_count += 1,
-                Player::O => self.o_count += 1
+                Player::X => self.x |= bit,
+                Player::O => self.o |= bit
             }
-            Ok(self.check_wins(pos))
+            Ok(self.check_wins())
         } else {
-            Err(InvalidMoveError)
+            Err(InvalidMoveError::SpaceTaken(position))
         }
     }
 
-    pub fn check_wins(&self, pos: (usize, usize)) -> BoardState {
-        if self.x_count < 3 && self.o_count < 3 {
-            BoardState::Ongoing
-        } else {
-            let mut row = 0;
-            let mut col = 0;
-            let mut diag = 0;
-            let mut rdiag = 0;
-            for i in 0..3 {
-                row += match &self.board[pos.1][i] { BoardSpace::Empty => 0, BoardSpace::Taken(x) => *x as i32};
-                col += match &self.board[i][pos.0] { BoardSpace::Empty => 0, BoardSpace::Taken(x) => *x as i32};
-                diag += match &self.board[i][i] { BoardSpace::Empty => 0, BoardSpace::Taken(x) => *x as i32};
-                rdiag += match &self.board[i][2-i] { BoardSpace::Empty => 0, BoardSpace::Taken(x) => *x as i32};
-            }
-            if row == 3 || col == 3 || diag == 3 || rdiag == 3 {
-                return BoardState::Concluded(BoardResult::XWin)
-            }
-            if row == -3 || col == -3 || diag == -3 || rdiag == -3 {
-                return BoardState::Concluded(BoardResult::OWin)
-            }
-            if self.x_count + self.o_count == 9 {
-                return BoardState::Concluded(BoardResult::Tie)
-            }
-
-            BoardState::Ongoing
+    fn remove(&mut self, pos: (usize, usize)) {
+        let bit = 1 << (pos.1 * 3 + pos.0);
+        self.x &= !bit;
+        self.o &= !bit;
+    }
 
+    pub fn check_wins(&self) -> BoardState {
+        if WINNING[self.x as usize] {
+            return BoardState::Concluded(BoardResult::XWin)
+        }
+        if WINNING[self.o as usize] {
+            return BoardState::Concluded(BoardResult::OWin)
+        }
+        if self.empty_spaces() == 0 {
+            return BoardState::Concluded(BoardResult::Tie)
         }
+
+        BoardState::Ongoing
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Player {
     X = 1,
     O = -1
 }
 
 impl Player {
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Self::X => 0,
+            Self::O => 1
+        }
+    }
     pub fn switch(self) -> Player {
         match self {
             Self::X => Self::O,
             Self::O => Self::X
         }
     }
-    pub fn random() -> Player {
-        let mut rng = thread_rng();
-        *[Self::X, Self::O].iter().choose(&mut rng).unwrap()
+    pub fn random(rng: &mut impl Rng) -> Player {
+        *[Self::X, Self::O].iter().choose(rng).unwrap()
     }
 }
 
@@ -294,14 +639,23 @@ pub enum BoardSpace {
     Taken(Player)
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BoardResult {
     XWin = 1,
     Tie = 0,
     OWin = -1
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+impl From<Player> for BoardResult {
+    fn from(player: Player) -> Self {
+        match player {
+            Player::X => BoardResult::XWin,
+            Player::O => BoardResult::OWin
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BoardState {
     Ongoing,
     Concluded(BoardResult)
@@ -322,4 +676,275 @@ impl std::fmt::Debug for PlayerType {
             Self::Online => write!(f, "Online"),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rand::{rngs::SmallRng, seq::{IteratorRandom, SliceRandom}, Rng, SeedableRng};
+
+    use super::*;
+
+    pub(crate) fn rulesets() -> Vec<Ruleset> {
+        let mut rulesets = Vec::new();
+        for i in 0..16 {
+            rulesets.push(Ruleset {
+                sent_to_finished: if i & 1 == 0 { SentToFinished::OpenBoards } else { SentToFinished::Anywhere },
+                tied_boards_count_for_both: i & 2 != 0,
+                finished_boards_playable: i & 4 != 0,
+                meta_draw: if i & 8 == 0 { MetaDraw::Tie } else { MetaDraw::MostBoards },
+            });
+        }
+        rulesets
+    }
+
+    // the moves of a game played out at random from the given state
+    pub(crate) fn random_game(rng: &mut impl Rng, state: &GameState) -> Vec<Position> {
+        let mut state = state.clone();
+        let mut moves = Vec::new();
+        while let Some(position) = state.moves().choose(rng) {
+            state.play(position).unwrap();
+            moves.push(position);
+        }
+        moves
+    }
+
+    // a plain array version of the standard rules to check the bitboards against
+    fn naive_winner(cells: [Option<Player>; 9]) -> Option<Player> {
+        const LINES: [[usize; 3]; 8] = [[0, 1, 2], [3, 4, 5], [6, 7, 8], [0, 3, 6], [1, 4, 7], [2, 5, 8], [0, 4, 8], [2, 4, 6]];
+        LINES.iter().find_map(|line| match cells[line[0]] {
+            Some(player) if cells[line[1]] == Some(player) && cells[line[2]] == Some(player) => Some(player),
+            _ => None,
+        })
+    }
+
+    fn naive_moves(cells: &[[Option<Player>; 9]; 9], results: &[Option<BoardResult>; 9], last: Option<Position>) -> Vec<Position> {
+        let target = last.map(|last| last.1.1 * 3 + last.1.0).filter(|&board| results[board].is_none());
+        let mut moves = Vec::new();
+        for (board, (cells, result)) in cells.iter().zip(results).enumerate() {
+            if result.is_some() || target.is_some_and(|target| target != board) {
+                continue;
+            }
+            for (cell, player) in cells.iter().enumerate() {
+                if player.is_none() {
+                    moves.push(Position((board % 3, board / 3), (cell % 3, cell / 3)));
+                }
+            }
+        }
+        moves
+    }
+
+    #[test]
+    fn bitboards_match_naive_rules() {
+        let mut rng = SmallRng::seed_from_u64(2);
+        for _ in 0..500 {
+            let mut game = GameState::new(Player::X, Ruleset::default());
+            let mut cells = [[None; 9]; 9];
+            let mut results = [None; 9];
+            let mut last = None;
+            let mut turn = Player::X;
+            loop {
+                let mut expected = naive_moves(&cells, &results, last);
+                let mut moves = game.get_possible_moves();
+                expected.sort_by_key(|position| position.index());
+                moves.sort_by_key(|position| position.index());
+                assert_eq!(moves, expected);
+                let position = *expected.choose(&mut rng).unwrap();
+                let board = position.0.1 * 3 + position.0.0;
+                cells[board][position.1.1 * 3 + position.1.0] = Some(turn);
+                if let Some(winner) = naive_winner(cells[board]) {
+                    results[board] = Some(if winner == Player::X { BoardResult::XWin } else { BoardResult::OWin });
+                } else if cells[board].iter().all(Option::is_some) {
+                    results[board] = Some(BoardResult::Tie);
+                }
+                let meta = results.map(|result| match result {
+                    Some(BoardResult::XWin) => Some(Player::X),
+                    Some(BoardResult::OWin) => Some(Player::O),
+                    _ => None,
+                });
+                let expected_state = match naive_winner(meta) {
+                    Some(Player::X) => BoardState::Concluded(BoardResult::XWin),
+                    Some(Player::O) => BoardState::Concluded(BoardResult::OWin),
+                    None if results.iter().all(Option::is_some) => BoardState::Concluded(BoardResult::Tie),
+                    None => BoardState::Ongoing,
+                };
+                assert_eq!(game.place(position.0, position.1), Ok(expected_state));
+                for (board, result) in results.iter().enumerate() {
+                    let state = result.map_or(BoardState::Ongoing, BoardState::Concluded);
+                    assert_eq!(game.mini_board_state((board % 3, board / 3)), state);
+                }
+                if expected_state != BoardState::Ongoing {
+                    assert!(game.get_possible_moves().is_empty());
+                    break;
+                }
+                last = Some(position);
+                turn = if turn == Player::X { Player::O } else { Player::X };
+            }
+        }
+    }
+
+    #[test]
+    fn unplace_round_trips_random_games() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for i in 0..1600 {
+            let ruleset = rulesets()[i % 16];
+            let mut game = GameState::new(Player::X, ruleset);
+            let mut history = Vec::new();
+            for position in random_game(&mut rng, &game) {
+                let before = game.clone();
+                let token = game.play(position).unwrap();
+                assert_eq!(token.result, game.board_state);
+                history.push((before, token));
+            }
+            while let Some((before, token)) = history.pop() {
+                game.unplace(token);
+                assert!(game == before);
+                assert_eq!(game.to_fen(), before.to_fen());
+                assert_eq!(game.move_mask(), before.move_mask());
+            }
+            assert!(game == GameState::new(Player::X, ruleset));
+        }
+    }
+
+    #[test]
+    fn zobrist_is_kept_in_step() {
+        let mut rng = SmallRng::seed_from_u64(5);
+        let mut seen = HashSet::new();
+        for i in 0..200 {
+            let mut game = GameState::new(if i % 2 == 0 { Player::X } else { Player::O }, rulesets()[i % 16]);
+            let mut tokens = Vec::new();
+            for position in random_game(&mut rng, &game) {
+                tokens.push(game.play(position).unwrap());
+                assert_eq!(game.zobrist(), game.compute_zobrist());
+                seen.insert((game.to_fen(), game.zobrist()));
+            }
+            while let Some(token) = tokens.pop() {
+                game.unplace(token);
+                assert_eq!(game.zobrist(), game.compute_zobrist());
+            }
+        }
+        let keys = seen.iter().map(|(_, hash)| *hash).collect::<HashSet<_>>();
+        let fens = seen.iter().map(|(fen, _)| fen.split(' ').take(3).collect::<Vec<_>>()).collect::<HashSet<_>>();
+        assert_eq!(keys.len(), fens.len());
+    }
+
+    #[test]
+    fn sent_to_a_finished_board() {
+        let fen = "xxx6/9/9/9/9/9/9/9/9 o -";
+        let standard = GameState::from_fen(fen).unwrap();
+        assert_eq!(standard.move_mask().count_ones(), 72);
+
+        let ruleset = Ruleset { sent_to_finished: SentToFinished::Anywhere, ..Ruleset::default() };
+        let mut anywhere = GameState::from_fen(&format!("{fen} a")).unwrap();
+        assert!(anywhere.ruleset() == ruleset);
+        assert_eq!(anywhere.move_mask().count_ones(), 78);
+        assert_eq!(anywhere.place((0, 0), (0, 1)), Ok(BoardState::Ongoing));
+        assert_eq!(anywhere.mini_board_state((0, 0)), BoardState::Concluded(BoardResult::XWin));
+        assert_eq!(anywhere.next_meta_move, Some((0, 1)));
+        assert_eq!(anywhere.place((0, 1), (0, 0)), Ok(BoardState::Ongoing));
+        assert_eq!(anywhere.next_meta_move, None);
+        assert_eq!(anywhere.place((1, 0), (0, 0)), Ok(BoardState::Ongoing));
+        assert_eq!(anywhere.next_meta_move, None);
+        assert_eq!(anywhere.place((0, 0), (1, 1)), Ok(BoardState::Ongoing));
+
+        let mut playable = GameState::from_fen(&format!("{fen} p")).unwrap();
+        playable.place((1, 1), (0, 0)).unwrap();
+        assert_eq!(playable.next_meta_move, Some((0, 0)));
+        assert_eq!(playable.place((0, 0), (0, 1)), Ok(BoardState::Ongoing));
+        assert_eq!(playable.mini_board_state((0, 0)), BoardState::Concluded(BoardResult::XWin));
+    }
+
+    #[test]
+    fn tied_boards_count_for_both() {
+        for (ruleset, result) in [(Ruleset::default(), BoardState::Ongoing), (Ruleset { tied_boards_count_for_both: true, ..Ruleset::default() }, BoardState::Concluded(BoardResult::XWin))] {
+            let mut state = GameState::new(Player::X, ruleset);
+            state.ties = 0b000_000_001;
+            state.meta.x = 0b000_000_010;
+            state.mini_boards[2].x = 0b000_000_011;
+            state.next_meta_move = Some((2, 0));
+            assert_eq!(state.place((2, 0), (2, 0)), Ok(result));
+        }
+
+        // tying the last board of two lines at once gives the game to the player who tied it
+        let mut state = GameState::new(Player::X, Ruleset { tied_boards_count_for_both: true, ..Ruleset::default() });
+        state.meta.x = 0b000_000_110;
+        state.meta.o = 0b001_001_000;
+        state.mini_boards[0] = Board { x: 0b010_001_101, o: 0b001_110_010 };
+        state.next_meta_move = Some((0, 0));
+        assert_eq!(state.place((0, 0), (2, 2)), Ok(BoardState::Concluded(BoardResult::XWin)));
+        assert_eq!(state.mini_board_state((0, 0)), BoardState::Concluded(BoardResult::Tie));
+    }
+
+    #[test]
+    fn meta_draw_by_most_boards() {
+        for (meta_draw, result) in [(MetaDraw::Tie, BoardResult::Tie), (MetaDraw::MostBoards, BoardResult::OWin)] {
+            let mut state = GameState::new(Player::O, Ruleset { meta_draw, ..Ruleset::default() });
+            state.meta.x = 0b010_001_101;
+            state.meta.o = 0b001_110_010;
+            state.mini_boards[8].o = 0b000_000_011;
+            state.next_meta_move = Some((2, 2));
+            assert_eq!(state.place((2, 2), (2, 0)), Ok(BoardState::Concluded(result)));
+            assert_eq!(state.mini_boards_won(), (4, 5));
+            assert_eq!(state.won_on_boards(), meta_draw == MetaDraw::MostBoards);
+        }
+    }
+
+    #[test]
+    fn meta_win_on_the_last_cell_ends_the_game() {
+        let mut game = GameState::from_fen("xxxxxxxox/6oxo/6ox1/9/9/9/9/9/9 x 3").unwrap();
+        assert_eq!(game.get_possible_moves(), vec![Position((2, 0), (2, 2))]);
+        assert_eq!(game.place((2, 0), (2, 2)), Ok(BoardState::Concluded(BoardResult::XWin)));
+        assert_eq!(game.mini_board_state((2, 0)), BoardState::Concluded(BoardResult::XWin));
+        assert_eq!(game.board_state, BoardState::Concluded(BoardResult::XWin));
+        assert!(game.get_possible_moves().is_empty());
+        assert_eq!(game.place((1, 1), (1, 1)), Err(InvalidMoveError::GameOver(Position((1, 1), (1, 1)))));
+    }
+
+    #[test]
+    fn no_moves_after_a_tie_with_open_mini_boards() {
+        let mut game = GameState::new(Player::X, Ruleset::default());
+        game.place((1, 1), (1, 1)).unwrap();
+        game.board_state = BoardState::Concluded(BoardResult::Tie);
+        assert_eq!(game.move_mask(), 0);
+        assert!(game.get_possible_moves().is_empty());
+        assert_eq!(game.place((1, 1), (0, 0)), Err(InvalidMoveError::GameOver(Position((1, 1), (0, 0)))));
+        assert!(game.turn == Player::O);
+    }
+
+    #[test]
+    fn concluded_random_games_accept_no_moves() {
+        let mut rng = SmallRng::seed_from_u64(4);
+        for _ in 0..1000 {
+            let mut game = GameState::new(Player::X, Ruleset::default());
+            for position in random_game(&mut rng, &game) {
+                game.place(position.0, position.1).unwrap();
+            }
+            assert!(matches!(game.board_state, BoardState::Concluded(_)));
+            for index in 0..81 {
+                let position = Position::from_index(index);
+                assert_eq!(game.place(position.0, position.1), Err(InvalidMoveError::GameOver(position)));
+            }
+        }
+    }
+
+    #[test]
+    fn state_at_replays_history() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut game = Game::new(Player::O, PlayerType::Local, PlayerType::Local);
+        let mut states = vec![game.state.clone()];
+        for position in random_game(&mut rng, &game.state) {
+            let player = game.state.turn;
+            game.place(position).unwrap();
+            assert!(game.history().last().unwrap().player == player);
+            states.push(game.state.clone());
+        }
+        assert_eq!(game.ply(), states.len() - 1);
+        for (ply, state) in states.iter().enumerate() {
+            assert!(game.state_at(ply).unwrap() == *state);
+        }
+        assert!(game.state_at(game.ply() + 1).is_none());
+
+        game.rewind(5);
+        assert_eq!(game.ply(), 5);
+        assert!(game.state == states[5]);
+    }
+}
@@ -0,0 +1,359 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use super::{BoardResult, BoardSpace, BoardState, GameState, MetaDraw, Player, Position, Ruleset, SentToFinished, FULL_BOARD, WINNING};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NotationError {
+    Empty,
+    InvalidColumn(char),
+    InvalidRow(char),
+    InvalidBoardIndex(char),
+    TrailingCharacters(String),
+    RankCount(usize),
+    RankLength { rank: usize, length: usize },
+    InvalidCell { rank: usize, character: char },
+    BothPlayersWon { board: usize },
+    MissingField(&'static str),
+    InvalidTurn(String),
+    InvalidForcedBoard(String),
+    ForcedBoardConcluded { board: usize },
+    InvalidRules(char),
+    InvalidMetaBoard(String),
+    MetaBoardMismatch { board: usize },
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty input"),
+            Self::InvalidColumn(c) => write!(f, "invalid column '{c}', expected a-i"),
+            Self::InvalidRow(c) => write!(f, "invalid row '{c}', expected 1-9"),
+            Self::InvalidBoardIndex(c) => write!(f, "invalid board index '{c}', expected 1-9"),
+            Self::TrailingCharacters(rest) => write!(f, "unexpected trailing input \"{rest}\""),
+            Self::RankCount(count) => write!(f, "expected 9 ranks, found {count}"),
+            Self::RankLength { rank, length } => write!(f, "rank {} describes {length} cells, expected 9", rank + 1),
+            Self::InvalidCell { rank, character } => write!(f, "invalid character '{character}' in rank {}", rank + 1),
+            Self::BothPlayersWon { board } => write!(f, "both players have a line in mini-board {}", board + 1),
+            Self::MissingField(field) => write!(f, "missing {field}"),
+            Self::InvalidTurn(turn) => write!(f, "invalid side to move \"{turn}\", expected x or o"),
+            Self::InvalidForcedBoard(board) => write!(f, "invalid forced board \"{board}\", expected - or 1-9"),
+            Self::ForcedBoardConcluded { board } => write!(f, "forced mini-board {} is already concluded", board + 1),
+            Self::InvalidRules(c) => write!(f, "unknown rule variant '{c}', expected a, t, p or m"),
+            Self::InvalidMetaBoard(meta) => write!(f, "invalid meta-board \"{meta}\", expected 9 of x, o, t or ."),
+            Self::MetaBoardMismatch { board } => write!(f, "meta-board result for mini-board {} does not match its cells", board + 1),
+        }
+    }
+}
+
+impl Error for NotationError {}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (x, y) = self.grid();
+        write!(f, "{}{}", (b'a' + x as u8) as char, y + 1)
+    }
+}
+
+fn parse_index(c: char, error: fn(char) -> NotationError) -> Result<usize, NotationError> {
+    match c {
+        '1'..='9' => Ok(c as usize - '1' as usize),
+        _ => Err(error(c)),
+    }
+}
+
+// accepts both "e5" grid coordinates and "5/5" meta/mini cell indices, counted 1-9 from the top left
+impl FromStr for Position {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let mut chars = s.chars();
+        let first = chars.next().ok_or(NotationError::Empty)?;
+        let position = if first.is_ascii_digit() {
+            let meta = parse_index(first, NotationError::InvalidBoardIndex)?;
+            match chars.next() {
+                Some('/') => (),
+                Some(c) => return Err(NotationError::TrailingCharacters(c.to_string() + chars.as_str())),
+                None => return Err(NotationError::MissingField("mini-board cell")),
+            }
+            let mini = parse_index(chars.next().ok_or(NotationError::MissingField("mini-board cell"))?, NotationError::InvalidBoardIndex)?;
+            Position::from_index(meta * 9 + mini)
+        } else {
+            let x = match first.to_ascii_lowercase() {
+                c @ 'a'..='i' => c as usize - 'a' as usize,
+                c => return Err(NotationError::InvalidColumn(c)),
+            };
+            let y = parse_index(chars.next().ok_or(NotationError::MissingField("row"))?, NotationError::InvalidRow)?;
+            Position::from_grid(x, y)
+        };
+        if !chars.as_str().is_empty() {
+            return Err(NotationError::TrailingCharacters(chars.as_str().to_string()));
+        }
+        Ok(position)
+    }
+}
+
+impl GameState {
+    // ranks of the 9x9 grid from the top, then the side to move and the forced mini-board (1-9 or -).
+    // non-standard rulesets add their variant letters and the meta-board results, which can then depend on move order
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for y in 0..9 {
+            if y != 0 {
+                fen.push('/');
+            }
+            let mut empty = 0;
+            for x in 0..9 {
+                let symbol = match self.get_space(Position::from_grid(x, y)) {
+                    BoardSpace::Empty => {
+                        empty += 1;
+                        continue;
+                    }
+                    BoardSpace::Taken(Player::X) => 'x',
+                    BoardSpace::Taken(Player::O) => 'o',
+                };
+                if empty != 0 {
+                    fen.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                fen.push(symbol);
+            }
+            if empty != 0 {
+                fen.push_str(&empty.to_string());
+            }
+        }
+        fen.push(' ');
+        fen.push(match self.turn {
+            Player::X => 'x',
+            Player::O => 'o',
+        });
+        fen.push(' ');
+        match self.next_meta_move {
+            Some((x, y)) => fen.push_str(&(y * 3 + x + 1).to_string()),
+            None => fen.push('-'),
+        }
+        if self.ruleset != Ruleset::default() {
+            fen.push(' ');
+            fen.push_str(&rules_to_string(self.ruleset));
+            fen.push(' ');
+            for i in 0..9 {
+                fen.push(match self.mini_board_state((i % 3, i / 3)) {
+                    BoardState::Ongoing => '.',
+                    BoardState::Concluded(BoardResult::XWin) => 'x',
+                    BoardState::Concluded(BoardResult::OWin) => 'o',
+                    BoardState::Concluded(BoardResult::Tie) => 't',
+                });
+            }
+        }
+        fen
+    }
+
+    pub fn from_fen(fen: &str) -> Result<GameState, NotationError> {
+        let mut fields = fen.split_whitespace();
+        let cells = fields.next().ok_or(NotationError::Empty)?;
+        let turn = match fields.next().ok_or(NotationError::MissingField("side to move"))? {
+            "x" | "X" => Player::X,
+            "o" | "O" => Player::O,
+            turn => return Err(NotationError::InvalidTurn(turn.to_string())),
+        };
+        let forced = fields.next().ok_or(NotationError::MissingField("forced board"))?;
+        let ruleset = match fields.next() {
+            Some(rules) => rules_from_str(rules)?,
+            None => Ruleset::default(),
+        };
+        let meta = fields.next();
+        let rest = fields.collect::<Vec<_>>();
+        if !rest.is_empty() {
+            return Err(NotationError::TrailingCharacters(rest.join(" ")));
+        }
+
+        let mut state = GameState::new(turn, ruleset);
+        let ranks = cells.split('/').collect::<Vec<_>>();
+        if ranks.len() != 9 {
+            return Err(NotationError::RankCount(ranks.len()));
+        }
+        for (y, rank) in ranks.into_iter().enumerate() {
+            let mut x = 0;
+            for character in rank.chars() {
+                let player = match character {
+                    '1'..='9' => {
+                        x += character as usize - '0' as usize;
+                        continue;
+                    }
+                    'x' | 'X' => Player::X,
+                    'o' | 'O' => Player::O,
+                    _ => return Err(NotationError::InvalidCell { rank: y, character }),
+                };
+                if x >= 9 {
+                    return Err(NotationError::RankLength { rank: y, length: x + 1 });
+                }
+                let Position(meta_pos, mini_pos) = Position::from_grid(x, y);
+                let board = &mut state.mini_boards[meta_pos.1 * 3 + meta_pos.0];
+                let bit = 1 << (mini_pos.1 * 3 + mini_pos.0);
+                match player {
+                    Player::X => board.x |= bit,
+                    Player::O => board.o |= bit,
+                }
+                x += 1;
+            }
+            if x != 9 {
+                return Err(NotationError::RankLength { rank: y, length: x });
+            }
+        }
+
+        match meta {
+            Some(meta) => {
+                if meta.chars().count() != 9 {
+                    return Err(NotationError::InvalidMetaBoard(meta.to_string()));
+                }
+                for (i, (result, board)) in meta.chars().zip(state.mini_boards).enumerate() {
+                    let (x_line, o_line) = (WINNING[board.x as usize], WINNING[board.o as usize]);
+                    let full = board.x | board.o == FULL_BOARD;
+                    let consistent = match result {
+                        'x' | 'X' => x_line,
+                        'o' | 'O' => o_line,
+                        't' | 'T' => full && !x_line && !o_line,
+                        '.' => !full && !x_line && !o_line,
+                        _ => return Err(NotationError::InvalidMetaBoard(meta.to_string())),
+                    };
+                    if !consistent {
+                        return Err(NotationError::MetaBoardMismatch { board: i });
+                    }
+                    match result {
+                        'x' | 'X' => state.meta.x |= 1 << i,
+                        'o' | 'O' => state.meta.o |= 1 << i,
+                        't' | 'T' => state.ties |= 1 << i,
+                        _ => (),
+                    }
+                }
+            }
+            None => {
+                for (i, board) in state.mini_boards.iter().enumerate() {
+                    if WINNING[board.x as usize] && WINNING[board.o as usize] {
+                        return Err(NotationError::BothPlayersWon { board: i });
+                    }
+                    match board.check_wins() {
+                        BoardState::Ongoing => (),
+                        BoardState::Concluded(BoardResult::XWin) => state.meta.x |= 1 << i,
+                        BoardState::Concluded(BoardResult::OWin) => state.meta.o |= 1 << i,
+                        BoardState::Concluded(BoardResult::Tie) => state.ties |= 1 << i,
+                    }
+                }
+            }
+        }
+        state.board_state = state.check_wins();
+
+        state.next_meta_move = match forced {
+            "-" => None,
+            _ => {
+                let mut chars = forced.chars();
+                let board = match (chars.next(), chars.next()) {
+                    (Some(c @ '1'..='9'), None) => c as usize - '1' as usize,
+                    _ => return Err(NotationError::InvalidForcedBoard(forced.to_string())),
+                };
+                if !state.can_send_to(board) {
+                    return Err(NotationError::ForcedBoardConcluded { board });
+                }
+                Some((board % 3, board / 3))
+            }
+        };
+        state.hash = state.compute_zobrist();
+        Ok(state)
+    }
+}
+
+fn rules_to_string(ruleset: Ruleset) -> String {
+    let mut rules = String::new();
+    if ruleset.sent_to_finished == SentToFinished::Anywhere {
+        rules.push('a');
+    }
+    if ruleset.tied_boards_count_for_both {
+        rules.push('t');
+    }
+    if ruleset.finished_boards_playable {
+        rules.push('p');
+    }
+    if ruleset.meta_draw == MetaDraw::MostBoards {
+        rules.push('m');
+    }
+    if rules.is_empty() {
+        rules.push('-');
+    }
+    rules
+}
+
+fn rules_from_str(rules: &str) -> Result<Ruleset, NotationError> {
+    let mut ruleset = Ruleset::default();
+    if rules == "-" {
+        return Ok(ruleset);
+    }
+    for c in rules.chars() {
+        match c {
+            'a' => ruleset.sent_to_finished = SentToFinished::Anywhere,
+            't' => ruleset.tied_boards_count_for_both = true,
+            'p' => ruleset.finished_boards_playable = true,
+            'm' => ruleset.meta_draw = MetaDraw::MostBoards,
+            _ => return Err(NotationError::InvalidRules(c)),
+        }
+    }
+    Ok(ruleset)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::game::tests::{random_game, rulesets};
+
+    use super::*;
+
+    #[test]
+    fn positions_round_trip() {
+        for index in 0..81 {
+            let position = Position::from_index(index);
+            assert_eq!(position.to_string().parse::<Position>(), Ok(position));
+            let (meta, mini) = (index / 9 + 1, index % 9 + 1);
+            assert_eq!(format!("{meta}/{mini}").parse::<Position>(), Ok(position));
+        }
+        assert_eq!("a1".parse(), Ok(Position((0, 0), (0, 0))));
+        assert_eq!("i9".parse(), Ok(Position((2, 2), (2, 2))));
+        assert_eq!("e5".parse(), Ok(Position((1, 1), (1, 1))));
+        assert_eq!("j1".parse::<Position>(), Err(NotationError::InvalidColumn('j')));
+        assert_eq!("a0".parse::<Position>(), Err(NotationError::InvalidRow('0')));
+        assert_eq!("a12".parse::<Position>(), Err(NotationError::TrailingCharacters("2".to_string())));
+    }
+
+    #[test]
+    fn fen_round_trips_random_games() {
+        assert_eq!(GameState::new(Player::X, Ruleset::default()).to_fen(), "9/9/9/9/9/9/9/9/9 x -");
+        let mut rng = SmallRng::seed_from_u64(2);
+        for i in 0..320 {
+            let mut game = GameState::new(Player::O, rulesets()[i % 16]);
+            for position in random_game(&mut rng, &game) {
+                let fen = game.to_fen();
+                assert!(GameState::from_fen(&fen).unwrap() == game, "{fen}");
+                game.place(position.0, position.1).unwrap();
+            }
+            assert!(GameState::from_fen(&game.to_fen()).unwrap() == game);
+        }
+    }
+
+    #[test]
+    fn fen_errors() {
+        assert_eq!(GameState::from_fen("").err(), Some(NotationError::Empty));
+        assert_eq!(GameState::from_fen("9/9/9/9/9/9/9/9 x -").err(), Some(NotationError::RankCount(8)));
+        assert_eq!(GameState::from_fen("9/9/9/8/9/9/9/9/9 x -").err(), Some(NotationError::RankLength { rank: 3, length: 8 }));
+        assert_eq!(GameState::from_fen("9/9/9/9/9/9/9/9/x9 x -").err(), Some(NotationError::RankLength { rank: 8, length: 10 }));
+        assert_eq!(GameState::from_fen("9/9/9/9/9/9/9/9/4z4 x -").err(), Some(NotationError::InvalidCell { rank: 8, character: 'z' }));
+        assert_eq!(GameState::from_fen("9/9/9/9/9/9/9/9/9 y -").err(), Some(NotationError::InvalidTurn("y".to_string())));
+        assert_eq!(GameState::from_fen("9/9/9/9/9/9/9/9/9 x").err(), Some(NotationError::MissingField("forced board")));
+        assert_eq!(GameState::from_fen("9/9/9/9/9/9/9/9/9 x 0").err(), Some(NotationError::InvalidForcedBoard("0".to_string())));
+        assert_eq!(GameState::from_fen("xxx6/9/9/9/9/9/9/9/9 o 1").err(), Some(NotationError::ForcedBoardConcluded { board: 0 }));
+        assert_eq!(GameState::from_fen("xxx6/ooo6/9/9/9/9/9/9/9 x -").err(), Some(NotationError::BothPlayersWon { board: 0 }));
+        assert_eq!(GameState::from_fen("9/9/9/9/9/9/9/9/9 x - at ......... 1").err(), Some(NotationError::TrailingCharacters("1".to_string())));
+        assert_eq!(GameState::from_fen("9/9/9/9/9/9/9/9/9 x - q").err(), Some(NotationError::InvalidRules('q')));
+        assert_eq!(GameState::from_fen("9/9/9/9/9/9/9/9/9 x - p ....").err(), Some(NotationError::InvalidMetaBoard("....".to_string())));
+        assert_eq!(GameState::from_fen("9/9/9/9/9/9/9/9/9 x - p ....x....").err(), Some(NotationError::MetaBoardMismatch { board: 4 }));
+        assert!(GameState::from_fen("xxx6/ooo6/9/9/9/9/9/9/9 x 1 p x........").is_ok());
+    }
+}
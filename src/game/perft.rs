@@ -0,0 +1,67 @@
+use super::{GameState, Position};
+
+impl GameState {
+    // counts the positions reachable in exactly depth moves. games that finish early are not leaves
+    pub fn perft(&self, depth: u32) -> u64 {
+        let mut game = self.clone();
+        perft(&mut game, depth)
+    }
+
+    // the perft count below each legal move, in move generation order
+    pub fn perft_divide(&self, depth: u32) -> Vec<(Position, u64)> {
+        let mut game = self.clone();
+        let mut out = Vec::new();
+        if depth == 0 {
+            return out;
+        }
+        for position in game.get_possible_moves() {
+            let token = game.play(position).expect("tried to play a move from get_possible_moves, and failed");
+            out.push((position, perft(&mut game, depth - 1)));
+            game.unplace(token);
+        }
+        out
+    }
+}
+
+fn perft(game: &mut GameState, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    if depth == 1 {
+        return game.moves().len() as u64;
+    }
+    let mut count = 0;
+    for position in game.get_possible_moves() {
+        let token = game.play(position).expect("tried to play a move from get_possible_moves, and failed");
+        count += perft(game, depth - 1);
+        game.unplace(token);
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::{Player, Ruleset};
+
+    use super::*;
+
+    #[test]
+    fn opening_perft() {
+        let game = GameState::new(Player::X, Ruleset::default());
+        let counts = [1, 81, 720, 6336, 55080, 473256, 4020960];
+        for (depth, &count) in counts.iter().enumerate() {
+            assert_eq!(game.perft(depth as u32), count, "depth {depth}");
+        }
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let game = GameState::new(Player::X, Ruleset::default());
+        let divide = game.perft_divide(3);
+        assert_eq!(divide.len(), 81);
+        assert_eq!(divide.iter().map(|(_, count)| count).sum::<u64>(), game.perft(3));
+        // the centre sends the opponent back to the centre board, where e5 is already taken, and every
+        // reply there sends x to an empty board
+        assert_eq!(divide.iter().find(|(position, _)| *position == "e5".parse().unwrap()).unwrap().1, 8 * 9);
+    }
+}
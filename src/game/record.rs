@@ -0,0 +1,361 @@
+use std::{error::Error, fmt, time::Duration};
+
+use web_time::SystemTime;
+
+use super::{BoardResult, BoardState, Game, GameState, InvalidMoveError, NotationError, Player, PlayerType, Position, Ruleset};
+
+#[derive(Debug)]
+pub enum RecordError {
+    MalformedHeader(String),
+    UnknownPlayerType(String),
+    InvalidThinkingTime(String),
+    InvalidDate(String),
+    InvalidResult(String),
+    InvalidPosition(NotationError),
+    InvalidNotation { ply: usize, notation: String, error: NotationError },
+    IllegalMove { ply: usize, position: Position, error: InvalidMoveError },
+    InvalidEval(String),
+    UnterminatedComment,
+    ResultMismatch { header: String, replayed: String },
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MalformedHeader(line) => write!(f, "malformed header \"{line}\""),
+            Self::UnknownPlayerType(name) => write!(f, "unknown player type \"{name}\""),
+            Self::InvalidThinkingTime(time) => write!(f, "invalid thinking time \"{time}\""),
+            Self::InvalidDate(date) => write!(f, "invalid date \"{date}\", expected YYYY.MM.DD"),
+            Self::InvalidResult(result) => write!(f, "invalid result \"{result}\""),
+            Self::InvalidPosition(error) => write!(f, "invalid starting position: {error}"),
+            Self::InvalidNotation { ply, notation, error } => write!(f, "move {} \"{notation}\": {error}", ply + 1),
+            Self::IllegalMove { ply, error, .. } => write!(f, "move {} is illegal: {error}", ply + 1),
+            Self::InvalidEval(eval) => write!(f, "invalid evaluation \"{eval}\""),
+            Self::UnterminatedComment => write!(f, "unterminated comment"),
+            Self::ResultMismatch { header, replayed } => write!(f, "result header says {header} but the moves end in {replayed}"),
+        }
+    }
+}
+
+impl Error for RecordError {}
+
+impl std::str::FromStr for PlayerType {
+    type Err = RecordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Local" => Ok(Self::Local),
+            "Mcts" => Ok(Self::Mcts),
+            "Online" => Ok(Self::Online),
+            _ => Err(RecordError::UnknownPlayerType(s.to_string())),
+        }
+    }
+}
+
+fn result_string(state: BoardState) -> &'static str {
+    match state {
+        BoardState::Ongoing => "*",
+        BoardState::Concluded(BoardResult::XWin) => "1-0",
+        BoardState::Concluded(BoardResult::OWin) => "0-1",
+        BoardState::Concluded(BoardResult::Tie) => "1/2-1/2",
+    }
+}
+
+fn is_result(token: &str) -> bool {
+    matches!(token, "*" | "1-0" | "0-1" | "1/2-1/2")
+}
+
+// days since 1970-01-01 to a proleptic gregorian (year, month, day), from Howard Hinnant's date algorithms
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn format_date(time: SystemTime) -> String {
+    let seconds = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+fn parse_date(date: &str) -> Result<SystemTime, RecordError> {
+    let error = || RecordError::InvalidDate(date.to_string());
+    let mut parts = date.split('.');
+    let (Some(year), Some(month), Some(day), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(error());
+    };
+    let year: i64 = year.parse().map_err(|_| error())?;
+    let month: u32 = month.parse().map_err(|_| error())?;
+    let day: u32 = day.parse().map_err(|_| error())?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(error());
+    }
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return Err(error());
+    }
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(days as u64 * 86400))
+}
+
+fn parse_header(line: &str) -> Result<(&str, String), RecordError> {
+    let malformed = || RecordError::MalformedHeader(line.to_string());
+    let inner = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')).ok_or_else(malformed)?;
+    let (key, value) = inner.split_once(' ').ok_or_else(malformed)?;
+    let value = value.trim().strip_prefix('"').and_then(|value| value.strip_suffix('"')).ok_or_else(malformed)?;
+    Ok((key, value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+fn write_header(record: &mut String, key: &str, value: &str) {
+    record.push_str(&format!("[{key} \"{}\"]\n", value.replace('\\', "\\\\").replace('"', "\\\"")));
+}
+
+enum Token<'a> {
+    Word(&'a str),
+    Comment(&'a str),
+}
+
+fn tokenize(movetext: &str) -> Result<Vec<Token<'_>>, RecordError> {
+    let mut tokens = Vec::new();
+    let mut rest = movetext.trim_start();
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix('{') {
+            // a backslash escapes the next character, so comments can contain '}'
+            let mut escaped = false;
+            let end = comment.find(|c: char| {
+                let end = c == '}' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end
+            }).ok_or(RecordError::UnterminatedComment)?;
+            tokens.push(Token::Comment(comment[..end].trim()));
+            rest = &comment[end + 1..];
+        } else {
+            let end = rest.find(|c: char| c.is_whitespace() || c == '{').unwrap_or(rest.len());
+            tokens.push(Token::Word(&rest[..end]));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+// "[%eval 0.42] text" -> (Some(0.42), Some("text"))
+fn parse_comment(comment: &str) -> Result<(Option<f32>, Option<String>), RecordError> {
+    let (eval, text) = match comment.strip_prefix("[%eval") {
+        Some(rest) => {
+            let end = rest.find(']').ok_or_else(|| RecordError::InvalidEval(comment.to_string()))?;
+            let eval = rest[..end].trim();
+            let eval = eval.parse().map_err(|_| RecordError::InvalidEval(eval.to_string()))?;
+            (Some(eval), rest[end + 1..].trim())
+        }
+        None => (None, comment),
+    };
+    Ok((eval, if text.is_empty() { None } else { Some(unescape_comment(text)) }))
+}
+
+fn escape_comment(comment: &str) -> String {
+    comment.replace('\\', "\\\\").replace('}', "\\}")
+}
+
+fn unescape_comment(comment: &str) -> String {
+    let mut unescaped = String::with_capacity(comment.len());
+    let mut chars = comment.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+    }
+    unescaped
+}
+
+impl Game {
+    pub fn to_record(&self) -> String {
+        let mut record = String::new();
+        write_header(&mut record, "X", &format!("{:?}", self.x));
+        write_header(&mut record, "O", &format!("{:?}", self.o));
+        if let Some(thinking_time) = self.thinking_time {
+            write_header(&mut record, "ThinkingTime", &format!("{:.3}", thinking_time.as_secs_f64()));
+        }
+        write_header(&mut record, "Date", &format_date(self.started));
+        write_header(&mut record, "Result", result_string(self.state.board_state));
+        if self.initial != GameState::new(Player::X, Ruleset::default()) {
+            write_header(&mut record, "Position", &self.initial.to_fen());
+        }
+        record.push('\n');
+
+        let mut movetext = Vec::new();
+        for (ply, move_) in self.history.iter().enumerate() {
+            if ply % 2 == 0 {
+                movetext.push(format!("{}.", ply / 2 + 1));
+            }
+            movetext.push(move_.position.to_string());
+            match (move_.eval, &move_.comment) {
+                (None, None) => (),
+                (Some(eval), None) => movetext.push(format!("{{[%eval {eval}]}}")),
+                (None, Some(comment)) => movetext.push(format!("{{{}}}", escape_comment(comment))),
+                (Some(eval), Some(comment)) => movetext.push(format!("{{[%eval {eval}] {}}}", escape_comment(comment))),
+            }
+        }
+        movetext.push(result_string(self.state.board_state).to_string());
+        record.push_str(&movetext.join(" "));
+        record.push('\n');
+        record
+    }
+
+    pub fn from_record(record: &str) -> Result<Game, RecordError> {
+        let mut x = PlayerType::Local;
+        let mut o = PlayerType::Local;
+        let mut thinking_time = None;
+        let mut started = None;
+        let mut result = None;
+        let mut initial = GameState::new(Player::X, Ruleset::default());
+
+        let mut lines = record.lines().map(str::trim).peekable();
+        while let Some(line) = lines.next_if(|line| line.is_empty() || line.starts_with('[')) {
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = parse_header(line)?;
+            match key {
+                "X" => x = value.parse()?,
+                "O" => o = value.parse()?,
+                "ThinkingTime" => {
+                    let seconds: f64 = value.parse().map_err(|_| RecordError::InvalidThinkingTime(value.clone()))?;
+                    thinking_time = Some(Duration::try_from_secs_f64(seconds).map_err(|_| RecordError::InvalidThinkingTime(value))?);
+                }
+                "Date" => started = Some(parse_date(&value)?),
+                "Result" => {
+                    if !is_result(&value) {
+                        return Err(RecordError::InvalidResult(value));
+                    }
+                    result = Some(value);
+                }
+                "Position" => initial = GameState::from_fen(&value).map_err(RecordError::InvalidPosition)?,
+                _ => (),
+            }
+        }
+
+        let mut game = Game::from_state(initial, x, o);
+        game.thinking_time = thinking_time;
+        if let Some(started) = started {
+            game.started = started;
+        }
+
+        let movetext = lines.collect::<Vec<_>>().join("\n");
+        for token in tokenize(&movetext)? {
+            match token {
+                Token::Comment(comment) => {
+                    let (eval, comment) = parse_comment(comment)?;
+                    if let Some(ply) = game.ply().checked_sub(1) {
+                        game.annotate(ply, comment, eval);
+                    }
+                }
+                Token::Word(word) if is_result(word) => {
+                    if result.is_none() {
+                        result = Some(word.to_string());
+                    }
+                }
+                Token::Word(word) if word.ends_with('.') && word.trim_end_matches('.').chars().all(|c| c.is_ascii_digit()) => (),
+                Token::Word(word) => {
+                    let ply = game.ply();
+                    let position: Position = word.parse().map_err(|error| RecordError::InvalidNotation { ply, notation: word.to_string(), error })?;
+                    game.place(position).map_err(|error| RecordError::IllegalMove { ply, position, error })?;
+                    game.history.last_mut().unwrap().timestamp = game.started;
+                }
+            }
+        }
+
+        let replayed = result_string(game.state.board_state);
+        if let Some(header) = result {
+            if header != replayed {
+                return Err(RecordError::ResultMismatch { header, replayed: replayed.to_string() });
+            }
+        }
+        Ok(game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::game::tests::{random_game, rulesets};
+
+    use super::*;
+
+    #[test]
+    fn records_round_trip() {
+        let mut rng = SmallRng::seed_from_u64(3);
+        let mut game = Game::from_state(GameState::new(Player::O, Ruleset::default()), PlayerType::Local, PlayerType::Mcts);
+        game.thinking_time = Some(Duration::from_millis(2500));
+        for position in random_game(&mut rng, &game.state) {
+            game.place(position).unwrap();
+        }
+        game.annotate(0, Some("opening".to_string()), None);
+        game.annotate(3, None, Some(-0.25));
+        game.annotate(4, Some("blunder".to_string()), Some(0.5));
+        game.annotate(5, Some("threat } here \\ {".to_string()), None);
+
+        let record = game.to_record();
+        let replayed = Game::from_record(&record).unwrap();
+        assert!(replayed.state == game.state);
+        assert!(replayed.initial_state() == game.initial_state());
+        assert_eq!(replayed.ply(), game.ply());
+        assert_eq!(replayed.thinking_time, game.thinking_time);
+        assert_eq!(format!("{:?}", replayed.o), "Mcts");
+        assert_eq!(replayed.history()[0].comment.as_deref(), Some("opening"));
+        assert_eq!(replayed.history()[3].eval, Some(-0.25));
+        assert_eq!(replayed.history()[4].comment.as_deref(), Some("blunder"));
+        assert_eq!(replayed.history()[5].comment.as_deref(), Some("threat } here \\ {"));
+        assert_eq!(replayed.to_record(), record);
+    }
+
+    #[test]
+    fn records_keep_the_ruleset() {
+        let mut rng = SmallRng::seed_from_u64(4);
+        for ruleset in rulesets().into_iter().skip(1) {
+            for _ in 0..20 {
+                let mut game = Game::from_state(GameState::new(Player::X, ruleset), PlayerType::Local, PlayerType::Local);
+                for position in random_game(&mut rng, &game.state) {
+                    game.place(position).unwrap();
+                }
+                let record = game.to_record();
+                assert!(record.contains("[Position "), "{record}");
+                let replayed = Game::from_record(&record).unwrap();
+                assert_eq!(replayed.initial_state().ruleset(), ruleset);
+                assert!(replayed.state == game.state);
+            }
+        }
+    }
+
+    #[test]
+    fn dates_round_trip() {
+        for date in ["1970.01.01", "2000.02.29", "2024.12.31", "2026.10.18"] {
+            assert_eq!(format_date(parse_date(date).unwrap()), date);
+        }
+        assert!(parse_date("2024.13.01").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_records() {
+        assert!(matches!(Game::from_record("[X \"Robot\"]\n\n*"), Err(RecordError::UnknownPlayerType(_))));
+        assert!(matches!(Game::from_record("1. e5 e6 e4"), Err(RecordError::IllegalMove { ply: 2, .. })));
+        assert!(matches!(Game::from_record("1. e5 z9"), Err(RecordError::InvalidNotation { ply: 1, .. })));
+        assert!(matches!(Game::from_record("[Result \"1-0\"]\n\n1. e5 e4"), Err(RecordError::ResultMismatch { .. })));
+        assert!(matches!(Game::from_record("1. e5 {unterminated"), Err(RecordError::UnterminatedComment)));
+    }
+}
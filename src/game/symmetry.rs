@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Board, GameState, Position};
+
+// the 8 rotations and reflections of the square. applied to the whole 9x9 grid they move
+// the meta board and every mini board in the same way, since x -> 8 - x is
+// (meta, mini) -> (2 - meta, 2 - mini) and so on
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Symmetry {
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+    Transpose,
+    AntiTranspose,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::FlipHorizontal,
+        Symmetry::FlipVertical,
+        Symmetry::Transpose,
+        Symmetry::AntiTranspose,
+    ];
+
+    pub fn inverse(self) -> Symmetry {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            other => other,
+        }
+    }
+
+    // n is the largest coordinate, 2 for a 3x3 board and 8 for the full grid
+    fn apply(self, (x, y): (usize, usize), n: usize) -> (usize, usize) {
+        match self {
+            Symmetry::Identity => (x, y),
+            Symmetry::Rotate90 => (n - y, x),
+            Symmetry::Rotate180 => (n - x, n - y),
+            Symmetry::Rotate270 => (y, n - x),
+            Symmetry::FlipHorizontal => (n - x, y),
+            Symmetry::FlipVertical => (x, n - y),
+            Symmetry::Transpose => (y, x),
+            Symmetry::AntiTranspose => (n - y, n - x),
+        }
+    }
+
+    fn apply_index(self, index: usize) -> usize {
+        let (x, y) = self.apply((index % 3, index / 3), 2);
+        y * 3 + x
+    }
+
+    fn apply_bits(self, bits: u16) -> u16 {
+        let mut out = 0;
+        for index in 0..9 {
+            if bits & 1 << index != 0 {
+                out |= 1 << self.apply_index(index);
+            }
+        }
+        out
+    }
+
+    fn apply_board(self, board: Board) -> Board {
+        Board { x: self.apply_bits(board.x), o: self.apply_bits(board.o) }
+    }
+}
+
+impl Position {
+    pub fn transform(self, symmetry: Symmetry) -> Position {
+        let (x, y) = symmetry.apply(self.grid(), 8);
+        Position::from_grid(x, y)
+    }
+}
+
+impl GameState {
+    pub fn transform(&self, symmetry: Symmetry) -> GameState {
+        let mut state = self.clone();
+        for index in 0..9 {
+            state.mini_boards[symmetry.apply_index(index)] = symmetry.apply_board(self.mini_boards[index]);
+        }
+        state.meta = symmetry.apply_board(self.meta);
+        state.ties = symmetry.apply_bits(self.ties);
+        state.next_meta_move = self.next_meta_move.map(|meta| symmetry.apply(meta, 2));
+        state.hash = state.compute_zobrist();
+        state
+    }
+
+    fn is_symmetric_under(&self, symmetry: Symmetry) -> bool {
+        self.next_meta_move == self.next_meta_move.map(|meta| symmetry.apply(meta, 2))
+            && self.meta == symmetry.apply_board(self.meta)
+            && self.ties == symmetry.apply_bits(self.ties)
+            && (0..9).all(|index| self.mini_boards[symmetry.apply_index(index)] == symmetry.apply_board(self.mini_boards[index]))
+    }
+
+    // the symmetries that leave this position unchanged, always including the identity
+    pub fn symmetries(&self) -> Vec<Symmetry> {
+        Symmetry::ALL.into_iter().filter(|&symmetry| self.is_symmetric_under(symmetry)).collect()
+    }
+
+    // the same key for every position in a symmetry class
+    pub fn symmetric_zobrist(&self) -> u64 {
+        Symmetry::ALL.into_iter().map(|symmetry| self.transform(symmetry).zobrist()).min().unwrap()
+    }
+
+    // the representative of this position's symmetry class, and the symmetry that maps this position onto it
+    pub fn canonical(&self) -> (GameState, Symmetry) {
+        Symmetry::ALL.into_iter()
+            .map(|symmetry| (self.transform(symmetry), symmetry))
+            .min_by_key(|(state, _)| state.zobrist())
+            .unwrap()
+    }
+
+    // one move out of each set of moves that lead to equivalent positions
+    pub fn distinct_moves(&self) -> Vec<Position> {
+        let symmetries = self.symmetries();
+        if symmetries.len() == 1 {
+            return self.get_possible_moves();
+        }
+        self.moves()
+            .filter(|&position| symmetries.iter().all(|&symmetry| position.transform(symmetry).index() >= position.index()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::game::{tests::{random_game, rulesets}, Player};
+
+    use super::*;
+
+    #[test]
+    fn transforms_commute_with_moves() {
+        let mut rng = SmallRng::seed_from_u64(11);
+        for i in 0..32 {
+            let mut game = GameState::new(Player::X, rulesets()[i % 16]);
+            for position in random_game(&mut rng, &game) {
+                for symmetry in Symmetry::ALL {
+                    let transformed = game.transform(symmetry);
+                    let mut moves = game.moves().map(|position| position.transform(symmetry).index()).collect::<Vec<_>>();
+                    moves.sort();
+                    assert_eq!(moves, transformed.moves().map(Position::index).collect::<Vec<_>>());
+                    assert_eq!(transformed.transform(symmetry.inverse()).to_fen(), game.to_fen());
+                    assert_eq!(transformed.symmetric_zobrist(), game.symmetric_zobrist());
+                    assert_eq!(transformed.canonical().0.to_fen(), game.canonical().0.to_fen());
+                }
+                let mut transformed = game.transform(Symmetry::Rotate90);
+                transformed.play(position.transform(Symmetry::Rotate90)).unwrap();
+                game.play(position).unwrap();
+                assert_eq!(transformed.to_fen(), game.transform(Symmetry::Rotate90).to_fen());
+            }
+        }
+    }
+
+    #[test]
+    fn opening_moves_up_to_symmetry() {
+        let game = GameState::new(Player::X, Default::default());
+        assert_eq!(game.symmetries().len(), 8);
+        assert_eq!(game.distinct_moves().len(), 15);
+
+        let mut game = game;
+        game.play("e5".parse().unwrap()).unwrap();
+        assert_eq!(game.distinct_moves().len(), 2);
+        game.play("d4".parse().unwrap()).unwrap();
+        assert_eq!(game.symmetries(), vec![Symmetry::Identity, Symmetry::Transpose]);
+        assert_eq!(game.distinct_moves().len(), game.moves().len() - 3);
+    }
+}
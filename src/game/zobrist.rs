@@ -0,0 +1,34 @@
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (state, z ^ (z >> 31))
+}
+
+const KEYS: [u64; 2 * 81 + 1 + 9] = {
+    let mut keys = [0; 2 * 81 + 1 + 9];
+    let mut state: u64 = 0x5eed;
+    let mut i = 0;
+    while i < keys.len() {
+        let (next, key) = splitmix64(state);
+        state = next;
+        keys[i] = key;
+        i += 1;
+    }
+    keys
+};
+
+// indexed by Player::X = 0, Player::O = 1 and then Position::index
+pub(super) fn cell(player: usize, index: usize) -> u64 {
+    KEYS[player * 81 + index]
+}
+
+pub(super) const O_TO_MOVE: u64 = KEYS[2 * 81];
+
+pub(super) fn forced(next_meta_move: Option<(usize, usize)>) -> u64 {
+    match next_meta_move {
+        Some((x, y)) => KEYS[2 * 81 + 1 + y * 3 + x],
+        None => 0,
+    }
+}
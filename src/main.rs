@@ -1,9 +1,9 @@
 use core::str;
 use std::{borrow::Borrow, future::IntoFuture, io::Read};
 
-use ai::mcts_worker::{mcts_worker, MctsInput};
+use ai::{mcts::{MctsConfig, Node}, mcts_worker::{mcts_worker, MctsInit, MctsInput}};
 use futures::executor::LocalPool;
-use game::{Board, BoardState, Game, GameState, InvalidMoveError, Player, PlayerType, Position};
+use game::{Board, BoardState, Game, GameState, InvalidMoveError, MetaDraw, Player, PlayerType, Position, Ruleset};
 use leptos::{For, html, NodeRef, create_node_ref, component, create_action, create_effect, create_signal, ev::click, logging::log, mount_to_body, update, view, Callback, CollectView, IntoSignal, IntoView, ReadSignal, Show, Signal, SignalGet, SignalGetUntracked, SignalSet, SignalUpdate, SignalWith, SignalWithUntracked};
 use rand::{distributions::Alphanumeric, Rng};
 use wasm_peers::{one_to_one::NetworkManager, ConnectionType, SessionId};
@@ -11,9 +11,7 @@ use web_sys::console;
 use web_time::SystemTime;
 use std::time::Duration;
 use serde::{Deserialize, Serialize};
-
-mod game;
-mod ai;
+use ultimate_tic_tac_toe::{ai, game};
 
 fn main() {
     if web_sys::window().is_some() {
@@ -33,6 +31,13 @@ fn Menu() -> impl IntoView {
     let (gamemode, set_gamemode) = create_signal(None);
     let (difficulty, set_difficulty) = create_signal(477);
     let difficulty_clone = difficulty.clone();
+    let (tie_break, set_tie_break) = create_signal(false);
+    let cores = (web_sys::window().unwrap().navigator().hardware_concurrency() as u32).max(1);
+    let (all_cores, set_all_cores) = create_signal(false);
+    let ruleset = move || Ruleset {
+        meta_draw: if tie_break.get() { MetaDraw::MostBoards } else { MetaDraw::Tie },
+        ..Ruleset::default()
+    };
     if let Ok(search) = web_sys::window().unwrap().location().search() {
         if search != ""  {
             set_gamemode.set(Some(Gamemode::Client));
@@ -52,13 +57,21 @@ fn Menu() -> impl IntoView {
                         />
                     <p> {move || {format!("{:.2}", 10f32.powf(difficulty_clone.get() as f32 / 1000f32))} } seconds for the ai to think</p> 
                     </div>
+                    <label>
+                        <input type="checkbox" prop:checked=tie_break on:change=move |ev| set_tie_break.set(leptos::event_target_checked(&ev))/>
+                        "Decide drawn games by mini-boards won"
+                    </label>
+                    <label>
+                        <input type="checkbox" prop:checked=all_cores on:change=move |ev| set_all_cores.set(leptos::event_target_checked(&ev))/>
+                        {format!("Let the AI think on all {cores} cores")}
+                    </label>
                 </div>
                 
             },
             Some(Gamemode::Ai) => {
                 view! {
                     <div class="post-menu">
-                        <Game difficulty=difficulty.get()/>
+                        <Game difficulty=difficulty.get() ruleset=ruleset() workers=if all_cores.get() { cores } else { 1 }/>
                     </div>
                 }
             },
@@ -66,7 +79,7 @@ fn Menu() -> impl IntoView {
                 view! {
                     
                     <div class="post-menu">
-                        <OnlineGame host=true/>
+                        <OnlineGame host=true ruleset=ruleset()/>
                     </div>
                 }
             },
@@ -74,7 +87,7 @@ fn Menu() -> impl IntoView {
                 view! {
                     
                     <div class="post-menu">
-                        <OnlineGame host=false/>
+                        <OnlineGame host=false ruleset=Ruleset::default()/>
                     </div>
                 }
             }
@@ -88,7 +101,9 @@ const STUN_SERVER_URL: &str = "stun:stun.relay.metered.ca:80";
 #[derive(Clone, Serialize, Deserialize)]
 enum Message {
     Text(ChatMessage),
-    Move(Position)
+    // the zobrist key after the move, so peers can notice a desync
+    Move(Position, u64),
+    Ruleset(Ruleset)
 }
 #[derive(Clone, Serialize, Deserialize)]
 struct ChatMessage {
@@ -97,11 +112,12 @@ struct ChatMessage {
     id: u128,
 }
 #[component]            
-fn OnlineGame(host: bool) -> impl IntoView {
+fn OnlineGame(host: bool, ruleset: Ruleset) -> impl IntoView {
     if host {
         
         let (connected, set_connected) = create_signal(false);
-        let (game, set_game) = create_signal(Game::new(Player::X, PlayerType::Local, PlayerType::Online));
+        let (game, set_game) = create_signal(Game::from_state(GameState::new(Player::X, ruleset), PlayerType::Local, PlayerType::Online));
+        let (error, set_error) = create_signal(None::<InvalidMoveError>);
         let (chat_history, set_chat_history) = create_signal(Vec::<ChatMessage>::new());
         let message_input: NodeRef<html::Input> = create_node_ref();
         
@@ -128,16 +144,26 @@ fn OnlineGame(host: bool) -> impl IntoView {
                     Message::Text(text) => {
                         create_effect(move |_| set_chat_history.update(|chat_history| { chat_history.push(text.clone()) }));
                     },
-                    Message::Move(pos) => {
-                        create_effect(move |_| set_game.update(|game| { game.state.place(pos.0, pos.1).unwrap(); }));
+                    Message::Move(pos, hash) => {
+                        create_effect(move |_| {
+                            let mut result = Ok(BoardState::Ongoing);
+                            set_game.update(|game| {
+                                result = game.place(pos);
+                                if result.is_ok() && game.state.zobrist() != hash {
+                                    log!("desync after {}: expected {:016x}, got {:016x}", pos, hash, game.state.zobrist());
+                                }
+                            });
+                            set_error.set(result.err());
+                        });
                     }
+                    Message::Ruleset(_) => (),
                 }
                 
             }
         };
         let server_clone = server.clone();
-        let send_move = move |pos: Position| {
-            server_clone.send_message(serde_json::to_string(&Message::Move(pos)).unwrap().as_str()).unwrap()
+        let send_move = move |pos: Position, hash: u64| {
+            server_clone.send_message(serde_json::to_string(&Message::Move(pos, hash)).unwrap().as_str()).unwrap()
         };
         let server_clone = server.clone();
         let submit_message = move |ev: leptos::ev::SubmitEvent| {
@@ -154,7 +180,11 @@ fn OnlineGame(host: bool) -> impl IntoView {
             server_clone.send_message(serde_json::to_string(&Message::Text(chat_message)).unwrap().as_str()).unwrap();
             input.set_value("");
         };
-        server.start(move ||{create_effect(move |_| set_connected.set(true));}, server_on_message);
+        let server_clone = server.clone();
+        server.start(move ||{
+            create_effect(move |_| set_connected.set(true));
+            server_clone.send_message(serde_json::to_string(&Message::Ruleset(ruleset)).unwrap().as_str()).unwrap();
+        }, server_on_message);
         
         
         let game_view = {
@@ -163,24 +193,30 @@ fn OnlineGame(host: bool) -> impl IntoView {
                 for column in 0..3 {
                     let send_move_clone = send_move.clone();
                     out.push(view! {<MiniBoard 
-                        board = Signal::derive(move || game.with(|game| game.state.mini_boards[row][column].clone()))
-                        state = Signal::derive(move || game.with(|game| game.state.meta_board[row][column]))
+                        board = Signal::derive(move || game.with(|game| game.state.mini_board((column, row))))
+                        state = Signal::derive(move || game.with(|game| game.state.mini_board_state((column, row))))
                         place = Callback::new(move |(mini_row, mini_column)| {
-                            set_game.update(|game| { game.state.place((column, row), (mini_column, mini_row)).unwrap(); log!("{:?}", ((column, row), (mini_row, mini_column)))} );
+                            let mut result = Ok(BoardState::Ongoing);
+                            set_game.update(|game| result = game.place(Position((column, row), (mini_column, mini_row))));
+                            if let Err(error) = result {
+                                set_error.set(Some(error));
+                                return;
+                            }
+                            set_error.set(None);
+                            log!("{:?}", ((column, row), (mini_row, mini_column)));
                             game.with_untracked(|game| {
                                 match match game.state.turn {
                                     Player::X => &game.x,
                                     Player::O => &game.o
                                 } {
                                     PlayerType::Local => (),
-                                    PlayerType::Online => send_move_clone(Position((column, row), (mini_column, mini_row))),
+                                    PlayerType::Online => send_move_clone(Position((column, row), (mini_column, mini_row)), game.state.zobrist()),
                                     _ => todo!(),
                                 };
                                 
                             });
                         })
-                        is_active = Signal::derive(move || game.with(|game| game.state.next_meta_move.map(move |pos| pos == (column, row)).unwrap_or(true)
-                            && matches!(game.state.meta_board[row][column], BoardState::Ongoing)
+                        is_active = Signal::derive(move || game.with(|game| game.state.is_board_playable((column, row))
                             && matches!(match game.state.turn {
                                 Player::X => &game.x,
                                 Player::O => &game.o
@@ -213,6 +249,8 @@ fn OnlineGame(host: bool) -> impl IntoView {
                         {game_view.clone()}
                     </div>
                 </div>
+                <p class="move-error">{move || error.get().map(|error| error.to_string())}</p>
+                <p class="result-caption">{move || game.with(|game| result_caption(&game.state))}</p>
                 <div class="chat">
                 <div class="chat-log">
                     <For each=chat_history key=|message| message.id children=move |ChatMessage { player, content, id }| {
@@ -241,6 +279,7 @@ fn OnlineGame(host: bool) -> impl IntoView {
 
         let (game, set_game) = create_signal(Game::new(Player::X, PlayerType::Online, PlayerType::Local));
 
+        let (error, set_error) = create_signal(None::<InvalidMoveError>);
         let (chat_history, set_chat_history) = create_signal(Vec::<ChatMessage>::new());
         let message_input: NodeRef<html::Input> = create_node_ref();
 
@@ -264,15 +303,27 @@ fn OnlineGame(host: bool) -> impl IntoView {
                     Message::Text(text) => {
                         create_effect(move |_| set_chat_history.update(|chat_history| { chat_history.push(text.clone()) }));
                     },
-                    Message::Move(pos) => {
-                        create_effect(move |_| set_game.update(|game| { game.state.place(pos.0, pos.1).unwrap(); }));
+                    Message::Move(pos, hash) => {
+                        create_effect(move |_| {
+                            let mut result = Ok(BoardState::Ongoing);
+                            set_game.update(|game| {
+                                result = game.place(pos);
+                                if result.is_ok() && game.state.zobrist() != hash {
+                                    log!("desync after {}: expected {:016x}, got {:016x}", pos, hash, game.state.zobrist());
+                                }
+                            });
+                            set_error.set(result.err());
+                        });
+                    }
+                    Message::Ruleset(ruleset) => {
+                        create_effect(move |_| set_game.set(Game::from_state(GameState::new(Player::X, ruleset), PlayerType::Online, PlayerType::Local)));
                     }
                 }
             }
         };
         let client_clone = client.clone();
-        let send_move = move |pos: Position| {
-            client_clone.send_message(serde_json::to_string(&Message::Move(pos)).unwrap().as_str()).unwrap()
+        let send_move = move |pos: Position, hash: u64| {
+            client_clone.send_message(serde_json::to_string(&Message::Move(pos, hash)).unwrap().as_str()).unwrap()
         };
         let client_clone = client.clone();
         
@@ -298,24 +349,30 @@ fn OnlineGame(host: bool) -> impl IntoView {
             for column in 0..3 {
                 let send_move_clone = send_move.clone();
                 out.push(view! {<MiniBoard 
-                    board = Signal::derive(move || game.with(|game| game.state.mini_boards[row][column].clone()))
-                    state = Signal::derive(move || game.with(|game| game.state.meta_board[row][column]))
+                    board = Signal::derive(move || game.with(|game| game.state.mini_board((column, row))))
+                    state = Signal::derive(move || game.with(|game| game.state.mini_board_state((column, row))))
                     place = Callback::new(move |(mini_row, mini_column)| {
-                        set_game.update(|game| { game.state.place((column, row), (mini_column, mini_row)).unwrap(); log!("{:?}", ((column, row), (mini_row, mini_column)))} );
+                        let mut result = Ok(BoardState::Ongoing);
+                        set_game.update(|game| result = game.place(Position((column, row), (mini_column, mini_row))));
+                        if let Err(error) = result {
+                            set_error.set(Some(error));
+                            return;
+                        }
+                        set_error.set(None);
+                        log!("{:?}", ((column, row), (mini_row, mini_column)));
                         game.with_untracked(|game| {
                             match match game.state.turn {
                                 Player::X => &game.x,
                                 Player::O => &game.o
                             } {
                                 PlayerType::Local => (),
-                                PlayerType::Online => send_move_clone(Position((column, row), (mini_column, mini_row))),
+                                PlayerType::Online => send_move_clone(Position((column, row), (mini_column, mini_row)), game.state.zobrist()),
                                 _ => todo!(),
                             };
                             
                         });
                     })
-                    is_active = Signal::derive(move || game.with(|game| game.state.next_meta_move.map(move |pos| pos == (column, row)).unwrap_or(true)
-                        && matches!(game.state.meta_board[row][column], BoardState::Ongoing)
+                    is_active = Signal::derive(move || game.with(|game| game.state.is_board_playable((column, row))
                         && matches!(match game.state.turn {
                             Player::X => &game.x,
                             Player::O => &game.o
@@ -348,6 +405,8 @@ fn OnlineGame(host: bool) -> impl IntoView {
                     {game_view.clone()}
                 </div>
             </div>
+            <p class="move-error">{move || error.get().map(|error| error.to_string())}</p>
+            <p class="result-caption">{move || game.with(|game| result_caption(&game.state))}</p>
             <div class="chat">
                 <div class="chat-log">
                     <For each=chat_history key=|message| message.id children=move |ChatMessage { player, content, id }| {
@@ -376,18 +435,35 @@ fn OnlineGame(host: bool) -> impl IntoView {
     }
 }
 #[component]
-fn Game(difficulty: i32) -> impl IntoView {
+fn Game(difficulty: i32, ruleset: Ruleset, workers: u32) -> impl IntoView {
 
-    let (game, set_game) = create_signal(Game::new(Player::X, PlayerType::Local, PlayerType::Mcts));
-    let (mcts_sender, mcts_reciever) = mcts_worker(10f32.powf(difficulty as f32 / 1000f32)).unwrap();
+    let thinking_time = 10f32.powf(difficulty as f32 / 1000f32);
+    let mut new_game = Game::from_state(GameState::new(Player::X, ruleset), PlayerType::Local, PlayerType::Mcts);
+    new_game.thinking_time = Some(Duration::from_secs_f32(thinking_time));
+    let (game, set_game) = create_signal(new_game);
+    let (error, set_error) = create_signal(None::<InvalidMoveError>);
+    let config = MctsConfig::default();
+    let mcts_workers = (0..workers).map(|_| mcts_worker(MctsInit { thinking_time, config, workers }).unwrap()).collect::<Vec<_>>();
     let mcts_action = create_action(move |pos: &Position| {
         let pos = pos.to_owned();
-        let mcts_sender = mcts_sender.clone();
-        let mcts_reciever = mcts_reciever.clone();
+        let mcts_workers = mcts_workers.clone();
         async move {
-            mcts_sender.send_async(MctsInput::TakeMove { board: game.get_untracked().state, previous_move: pos }).await.unwrap();
-            let best_move = mcts_reciever.recv_async().await.unwrap();
-            set_game.update(|game| { game.state.place(best_move.0, best_move.1).unwrap(); });
+            let board = game.get_untracked().state;
+            for (mcts_sender, _) in &mcts_workers {
+                mcts_sender.send_async(MctsInput::TakeMove { board: board.clone(), previous_move: pos }).await.unwrap();
+            }
+            // every worker searches a tree of its own, so their root statistics are added up before picking
+            let mut searches = Vec::with_capacity(mcts_workers.len());
+            for (_, mcts_reciever) in &mcts_workers {
+                searches.push(mcts_reciever.recv_async().await.unwrap());
+            }
+            let best_move = Node::from_child_stats(searches).final_move(&config).unwrap();
+            for (mcts_sender, _) in &mcts_workers {
+                mcts_sender.send_async(MctsInput::Played(best_move)).await.unwrap();
+            }
+            let mut result = Ok(BoardState::Ongoing);
+            set_game.update(|game| result = game.place(best_move));
+            set_error.set(result.err());
         }
     });
     
@@ -396,10 +472,17 @@ fn Game(difficulty: i32) -> impl IntoView {
         for row in 0..3 {
             for column in 0..3 {
                 out.push(view! {<MiniBoard 
-                    board = Signal::derive(move || game.with(|game| game.state.mini_boards[row][column].clone()))
-                    state = Signal::derive(move || game.with(|game| game.state.meta_board[row][column]))
+                    board = Signal::derive(move || game.with(|game| game.state.mini_board((column, row))))
+                    state = Signal::derive(move || game.with(|game| game.state.mini_board_state((column, row))))
                     place = Callback::new(move |(mini_row, mini_column)| {
-                        set_game.update(|game| { game.state.place((column, row), (mini_column, mini_row)).unwrap(); log!("{:?}", ((column, row), (mini_row, mini_column)))} );
+                        let mut result = Ok(BoardState::Ongoing);
+                        set_game.update(|game| result = game.place(Position((column, row), (mini_column, mini_row))));
+                        if let Err(error) = result {
+                            set_error.set(Some(error));
+                            return;
+                        }
+                        set_error.set(None);
+                        log!("{:?}", ((column, row), (mini_row, mini_column)));
                         game.with_untracked(|game| {
                             match match game.state.turn {
                                 Player::X => &game.x,
@@ -417,8 +500,7 @@ fn Game(difficulty: i32) -> impl IntoView {
                             
                         });
                     })
-                    is_active = Signal::derive(move || game.with(|game| game.state.next_meta_move.map(move |pos| pos == (column, row)).unwrap_or(true)
-                        && matches!(game.state.meta_board[row][column], BoardState::Ongoing)
+                    is_active = Signal::derive(move || game.with(|game| game.state.is_board_playable((column, row))
                         && matches!(match game.state.turn {
                             Player::X => &game.x,
                             Player::O => &game.o
@@ -450,11 +532,21 @@ fn Game(difficulty: i32) -> impl IntoView {
             {game_view}
         </div>
     </div>
+    <p class="move-error">{move || error.get().map(|error| error.to_string())}</p>
+    <p class="result-caption">{move || game.with(|game| result_caption(&game.state))}</p>
         
     }
 
 }
 
+fn result_caption(state: &GameState) -> Option<String> {
+    if !state.won_on_boards() {
+        return None;
+    }
+    let (x, o) = state.mini_boards_won();
+    Some(format!("Drawn meta-board, decided on mini-boards won: X {x} - O {o}"))
+}
+
 #[component]
 fn MiniBoard(board: Signal<Board>, state: Signal<BoardState>, #[prop(into)] place: Callback<(usize, usize)>, is_active: Signal<bool>) -> impl IntoView {
     let mut spaces = Vec::with_capacity(9);
@@ -464,7 +556,7 @@ fn MiniBoard(board: Signal<Board>, state: Signal<BoardState>, #[prop(into)] plac
                 view ! { 
                     <div class="board-space"
                     class:inactive=move || !is_active()
-                    class:taken=move || board.with(|board| matches!(board.board[row][column], game::BoardSpace::Taken(_)))
+                    class:taken=move || board.with(|board| matches!(board.get_space((column, row)), game::BoardSpace::Taken(_)))
                     on:click=move |_| {
                         place((row, column)); 
                     }
@@ -472,7 +564,7 @@ fn MiniBoard(board: Signal<Board>, state: Signal<BoardState>, #[prop(into)] plac
                         place((row, column)); 
                     }>
                         {   
-                            move || match board().board[row][column] {
+                            move || match board().get_space((column, row)) {
                                 game::BoardSpace::Taken(Player::X) => Some(view!{<img src="x.svg"/>}),
                                 game::BoardSpace::Taken(Player::O) => Some(view!{<img src="o.svg"/>}),
                                 _ => None